@@ -0,0 +1,326 @@
+use crate::texture::{Options, Texture};
+use image::RgbaImage;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Packs many small images into a few large textures (pages).
+/// When an image does not fit in any page a new page is made.
+///
+/// With `options.mipmap` the mip levels of a page are regenerated
+/// once it is used after inserts, by `page` or `finish`, not on
+/// every insert.
+///
+/// Images are flipped like `Texture::from_path` does, so the
+/// uv rect of a region has its (u0, v0) at the lower left corner
+/// of the image, and can be used like the uvs of a plain texture.
+pub struct TextureAtlas<K> {
+    size: (u32, u32),
+    padding: u32,
+    options: Options,
+    pages: Vec<Page>,
+    regions: HashMap<K, AtlasRegion>,
+}
+
+struct Page {
+    texture: Texture,
+    packer: SkylinePacker,
+    // inserted into since the mip levels were generated
+    dirty: Cell<bool>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtlasRegion {
+    pub page: usize,
+    /// lower left corner in texels, without the padding
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// [u0, v0, u1, v1]
+    pub uv: [f32; 4],
+}
+
+impl<K: Hash + Eq> TextureAtlas<K> {
+    /// `padding` texels around every image are filled by extruding
+    /// its edges, so linear filtering does not bleed in neighbours.
+    pub fn new(size: (u32, u32), padding: u32, options: Options) -> Self {
+        Self {
+            size,
+            padding,
+            options,
+            pages: Vec::new(),
+            regions: HashMap::new(),
+        }
+    }
+
+    /// Packs and uploads the image. Inserting a key that is already
+    /// in the atlas returns its existing region.
+    pub fn insert(&mut self, key: K, img: &RgbaImage) -> Result<AtlasRegion, String> {
+        if let Some(region) = self.regions.get(&key) {
+            return Ok(*region);
+        }
+        let (width, height) = img.dimensions();
+        if width == 0 || height == 0 {
+            return Err("Cannot add an empty image to the atlas".into());
+        }
+        let pad = |d: u32| self.padding.checked_mul(2).and_then(|p| d.checked_add(p));
+        let padded = match (pad(width), pad(height)) {
+            (Some(w), Some(h)) if w <= self.size.0 && h <= self.size.1 => (w, h),
+            _ => {
+                return Err(format!(
+                    "Image of {}x{} (+{} padding) does not fit in an atlas page of {}x{}",
+                    width, height, self.padding, self.size.0, self.size.1
+                ))
+            }
+        };
+
+        let fit = self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .find_map(|(i, page)| page.packer.pack(padded).map(|pos| (i, pos)));
+        let (page, (x, y)) = match fit {
+            Some(fit) => fit,
+            None => {
+                let mut packer = SkylinePacker::new(self.size);
+                let pos = packer.pack(padded).expect("[ERROR] Empty page must fit");
+                self.pages.push(Page {
+                    texture: Texture::empty(self.options, self.size),
+                    packer,
+                    dirty: Cell::new(false),
+                });
+                (self.pages.len() - 1, pos)
+            }
+        };
+
+        let flipped = image::imageops::flip_vertical(img);
+        let data = extrude(&flipped, self.padding);
+        self.pages[page]
+            .texture
+            .update_region(&data, (x, y), padded);
+        self.pages[page].dirty.set(self.options.mipmap);
+
+        let (x, y) = (x + self.padding, y + self.padding);
+        let (w, h) = (self.size.0 as f32, self.size.1 as f32);
+        let region = AtlasRegion {
+            page,
+            x,
+            y,
+            width,
+            height,
+            uv: [
+                x as f32 / w,
+                y as f32 / h,
+                (x + width) as f32 / w,
+                (y + height) as f32 / h,
+            ],
+        };
+        self.regions.insert(key, region);
+        Ok(region)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&AtlasRegion> {
+        self.regions.get(key)
+    }
+
+    /// Regenerates the mip levels first if the page changed.
+    pub fn page(&self, page: usize) -> &Texture {
+        let page = &self.pages[page];
+        if page.dirty.replace(false) {
+            page.texture.generate_mipmap();
+        }
+        &page.texture
+    }
+
+    /// Regenerates the mip levels of every changed page, f.i. once
+    /// all images are inserted.
+    pub fn finish(&self) {
+        for i in 0..self.pages.len() {
+            self.page(i);
+        }
+    }
+
+    pub fn n_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+/// Copies the image into the middle of a buffer that is `padding`
+/// texels larger on every side, repeating the edge texels outwards.
+fn extrude(img: &RgbaImage, padding: u32) -> Vec<u8> {
+    let (width, height) = img.dimensions();
+    let (pw, ph) = (width + 2 * padding, height + 2 * padding);
+    let mut data = Vec::with_capacity((pw * ph * 4) as usize);
+    for y in 0..ph {
+        let sy = (y.saturating_sub(padding)).min(height - 1);
+        for x in 0..pw {
+            let sx = (x.saturating_sub(padding)).min(width - 1);
+            data.extend_from_slice(&img.get_pixel(sx, sy).0);
+        }
+    }
+    data
+}
+
+/// Bottom-left skyline rectangle packer. The skyline is a list of
+/// horizontal segments covering the full width, every rect is put
+/// on top of the segments where it ends lowest.
+pub struct SkylinePacker {
+    size: (u32, u32),
+    // (x, y, width), sorted on x
+    skyline: Vec<(u32, u32, u32)>,
+}
+
+impl SkylinePacker {
+    pub fn new(size: (u32, u32)) -> Self {
+        Self {
+            size,
+            skyline: vec![(0, 0, size.0)],
+        }
+    }
+
+    /// Returns the lower left corner of the packed rect,
+    /// or None when it does not fit anymore.
+    pub fn pack(&mut self, (width, height): (u32, u32)) -> Option<(u32, u32)> {
+        let mut best: Option<(usize, u32)> = None;
+        let mut best_key = (u32::MAX, u32::MAX);
+        for i in 0..self.skyline.len() {
+            if let Some(y) = self.fit(i, width, height) {
+                let key = (y + height, self.skyline[i].2);
+                if key < best_key {
+                    best_key = key;
+                    best = Some((i, y));
+                }
+            }
+        }
+        let (i, y) = best?;
+        let x = self.skyline[i].0;
+        self.add(i, x, y + height, width);
+        Some((x, y))
+    }
+
+    fn fit(&self, i: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.skyline[i].0;
+        if x.checked_add(width)? > self.size.0 {
+            return None;
+        }
+        let mut y = 0;
+        let mut covered = 0;
+        for &(_, sy, sw) in &self.skyline[i..] {
+            y = y.max(sy);
+            if y.checked_add(height)? > self.size.1 {
+                return None;
+            }
+            covered += sw;
+            if covered >= width {
+                break;
+            }
+        }
+        Some(y)
+    }
+
+    fn add(&mut self, i: usize, x: u32, y: u32, width: u32) {
+        self.skyline.insert(i, (x, y, width));
+        let right = x + width;
+        // shrink or drop the segments now covered by the new one
+        while i + 1 < self.skyline.len() {
+            let (sx, sy, sw) = self.skyline[i + 1];
+            if sx >= right {
+                break;
+            }
+            if sx + sw <= right {
+                self.skyline.remove(i + 1);
+            } else {
+                self.skyline[i + 1] = (right, sy, sx + sw - right);
+                break;
+            }
+        }
+        // merge neighbours of equal height
+        let mut j = 0;
+        while j + 1 < self.skyline.len() {
+            if self.skyline[j].1 == self.skyline[j + 1].1 {
+                self.skyline[j].2 += self.skyline[j + 1].2;
+                self.skyline.remove(j + 1);
+            } else {
+                j += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Rect = (u32, u32, u32, u32);
+
+    fn overlap(a: Rect, b: Rect) -> bool {
+        a.0 < b.0 + b.2 && b.0 < a.0 + a.2 && a.1 < b.1 + b.3 && b.1 < a.1 + a.3
+    }
+
+    #[test]
+    fn fills_exactly() {
+        let mut packer = SkylinePacker::new((64, 64));
+        let mut corners = (0..4)
+            .map(|_| packer.pack((32, 32)).unwrap())
+            .collect::<Vec<_>>();
+        corners.sort_unstable();
+        assert_eq!(corners, vec![(0, 0), (0, 32), (32, 0), (32, 32)]);
+        assert_eq!(packer.pack((1, 1)), None);
+    }
+
+    #[test]
+    fn rejects_too_large() {
+        let mut packer = SkylinePacker::new((64, 32));
+        assert_eq!(packer.pack((65, 1)), None);
+        assert_eq!(packer.pack((1, 33)), None);
+        assert_eq!(packer.pack((64, 32)), Some((0, 0)));
+    }
+
+    #[test]
+    fn huge_rects_do_not_overflow() {
+        let mut packer = SkylinePacker::new((64, 64));
+        assert_eq!(packer.pack((10, 10)), Some((0, 0)));
+        assert_eq!(packer.pack((u32::MAX, 1)), None);
+        assert_eq!(packer.pack((1, u32::MAX)), None);
+        assert_eq!(packer.pack((u32::MAX, u32::MAX)), None);
+        assert_eq!(packer.pack((10, 10)), Some((10, 0)));
+    }
+
+    #[test]
+    fn puts_rects_lowest() {
+        let mut packer = SkylinePacker::new((100, 100));
+        assert_eq!(packer.pack((60, 50)), Some((0, 0)));
+        assert_eq!(packer.pack((40, 10)), Some((60, 0)));
+        // fits next to the first rect, on top of the second
+        assert_eq!(packer.pack((40, 20)), Some((60, 10)));
+        // too wide for that gap, goes on top
+        assert_eq!(packer.pack((70, 10)), Some((0, 50)));
+    }
+
+    #[test]
+    fn no_overlaps_in_bounds() {
+        let size = (256, 256);
+        let mut packer = SkylinePacker::new(size);
+        let mut packed: Vec<Rect> = Vec::new();
+        // deterministic pseudo random sizes
+        let mut seed = 12345u32;
+        for _ in 0..500 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let w = 1 + (seed >> 16) % 40;
+            let h = 1 + (seed >> 8) % 40;
+            if let Some((x, y)) = packer.pack((w, h)) {
+                let rect = (x, y, w, h);
+                assert!(x + w <= size.0 && y + h <= size.1);
+                assert!(packed.iter().all(|&other| !overlap(rect, other)));
+                packed.push(rect);
+            }
+        }
+        let area: u32 = packed.iter().map(|r| r.2 * r.3).sum();
+        assert!(area > size.0 * size.1 / 2, "only {} texels used", area);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{
+    sampler::Sampler,
+    shader::ShaderProgram,
+    state,
+    texture::{self, Texture},
+};
+
+/// Assigns texture units to (texture, sampler) pairs for a drawcall,
+/// and points the sampler uniforms of the program at them.
+///
+/// Logic:
+///     Call `begin` before setting up a drawcall, which frees all
+///     units for assignment but remembers what is bound to them.
+///     `bind` then prefers a unit that already holds the same pair,
+///     so nothing is rebound when consecutive draws share textures.
+///     What is bound comes from the `state` cache, which also sees
+///     binds outside the binder and forgets deleted textures and
+///     samplers, after raw GL binds call `state::invalidate`.
+pub struct TextureBinder {
+    // the units handed out since the last `begin`
+    used: Vec<bool>,
+}
+
+impl Default for TextureBinder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextureBinder {
+    pub fn new() -> Self {
+        let n_units = texture::max_units() as usize;
+        Self {
+            used: vec![false; n_units],
+        }
+    }
+
+    pub fn begin(&mut self) {
+        self.used.iter_mut().for_each(|u| *u = false);
+    }
+
+    pub fn n_units(&self) -> usize {
+        self.used.len()
+    }
+
+    /// Binds the pair to a free unit and sets the `uniform`
+    /// sampler of the (bound) program to it. Returns the unit.
+    pub fn bind(
+        &mut self,
+        program: &mut ShaderProgram,
+        uniform: &str,
+        texture: &Texture,
+        sampler: Option<&Sampler>,
+    ) -> Result<u32, &'static str> {
+        let unit = self.bind_unit(texture, sampler)?;
+        program.set_int(uniform, unit as i32);
+        Ok(unit)
+    }
+
+    /// Like `bind`, but leaves setting the uniform to the caller.
+    pub fn bind_unit(
+        &mut self,
+        texture: &Texture,
+        sampler: Option<&Sampler>,
+    ) -> Result<u32, &'static str> {
+        let (texture, sampler) = (texture.id(), sampler.map_or(0, |s| s.id()));
+        let free = |i: &u32| !self.used[*i as usize];
+        let n_units = self.used.len() as u32;
+
+        let reusable = (0..n_units).filter(free).find(|&i| {
+            state::bound_texture(i, gl::TEXTURE_2D) == Some(texture)
+                && state::bound_sampler(i) == Some(sampler)
+        });
+        let unit = match reusable {
+            Some(unit) => unit,
+            None => {
+                // prefer untouched units, so pairs bound earlier survive longer
+                let unit = (0..n_units)
+                    .filter(free)
+                    .min_by_key(|&i| state::bound_texture(i, gl::TEXTURE_2D).unwrap_or(0) != 0)
+                    .ok_or("All texture units are in use for this drawcall")?;
+                state::bind_texture_to(unit, gl::TEXTURE_2D, texture);
+                state::bind_sampler(unit, sampler);
+                unit
+            }
+        };
+        self.used[unit as usize] = true;
+        Ok(unit)
+    }
+}
@@ -1,9 +1,38 @@
+use crate::state;
+use std::marker::PhantomData;
+use std::ops::{BitOr, Deref, DerefMut};
+
 pub struct Buffer {
     id: u32,
     buffertype: gl::types::GLenum,
     drawtype: gl::types::GLenum,
+    // in bytes, as last allocated
+    size: usize,
+    // made with glBufferStorage, cannot be reallocated
+    immutable: bool,
 }
 
+/// Plain data that can be viewed in mapped buffer memory: every bit
+/// pattern is a valid value and there is no padding, like the types
+/// of the `bytemuck` crate.
+///
+/// # Safety
+/// Only implement this for `#[repr(C)]` structs of `Pod` fields
+/// without padding between or after them.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for i64 {}
+unsafe impl Pod for f32 {}
+unsafe impl Pod for f64 {}
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
 impl Buffer {
     pub fn new(buffertype: BufferType, drawtype: DrawType) -> Self {
         let mut id = 0;
@@ -14,28 +43,65 @@ impl Buffer {
             buffertype: buffertype as u32,
             drawtype: drawtype as u32,
             id,
+            size: 0,
+            immutable: false,
         }
     }
 
     pub fn bind(&self) {
-        unsafe {
-            gl::BindBuffer(self.buffertype, self.id);
-        }
+        state::bind_buffer(self.buffertype, self.id);
     }
 
     pub fn unbind(&self) {
-        unsafe {
-            gl::BindBuffer(self.buffertype, 0);
-        }
+        state::bind_buffer(self.buffertype, 0);
     }
 
     /// F.i. for binding a uniform buffer to a certain binding point.
     /// layout (std140, binding=thisbinding) uniform { ... }
     pub fn set_binding(&self, binding: usize) {
         unsafe { gl::BindBufferBase(self.buffertype, binding as u32, self.id) }
+        state::bound_indexed(self.buffertype, self.id);
+    }
+
+    /// Binds `size` bytes from `offset` to an indexed binding point, for
+    /// uniform, storage, atomic counter and transform feedback buffers.
+    /// `offset` must be a multiple of `offset_alignment`.
+    pub fn set_binding_range(&self, binding: usize, offset: usize, size: usize) {
+        let alignment = self.offset_alignment();
+        debug_assert!(
+            offset / alignment * alignment == offset,
+            "[ERROR] Binding range offset is not aligned"
+        );
+        unsafe {
+            gl::BindBufferRange(
+                self.buffertype,
+                binding as u32,
+                self.id,
+                offset as isize,
+                size as isize,
+            )
+        }
+        state::bound_indexed(self.buffertype, self.id);
+    }
+
+    /// The alignment `set_binding_range` offsets need for this buffer type.
+    pub fn offset_alignment(&self) -> usize {
+        let query = match self.buffertype {
+            gl::UNIFORM_BUFFER => gl::UNIFORM_BUFFER_OFFSET_ALIGNMENT,
+            gl::SHADER_STORAGE_BUFFER => gl::SHADER_STORAGE_BUFFER_OFFSET_ALIGNMENT,
+            // atomic counter offsets must be a multiple of 4
+            gl::ATOMIC_COUNTER_BUFFER | gl::TRANSFORM_FEEDBACK_BUFFER => return 4,
+            _ => return 1,
+        };
+        let mut alignment = 0;
+        unsafe {
+            gl::GetIntegerv(query, &mut alignment);
+        }
+        alignment.max(1) as usize
     }
 
-    pub fn init(&self, size: usize) {
+    pub fn init(&mut self, size: usize) {
+        self.set_size(size);
         unsafe {
             gl::BufferData(
                 self.buffertype,
@@ -46,7 +112,26 @@ impl Buffer {
         }
     }
 
-    pub fn buffer<T>(&self, content: &[T]) {
+    /// Reallocates storage of the same size, dropping the contents.
+    /// The driver hands out fresh memory while the gpu may still read
+    /// the old one, so streaming writes do not have to wait.
+    pub fn orphan(&mut self) {
+        // same size, but still panics on immutable storage
+        self.set_size(self.size);
+        unsafe {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.id);
+            gl::BufferData(
+                gl::COPY_WRITE_BUFFER,
+                self.size() as isize,
+                std::ptr::null(),
+                self.drawtype,
+            );
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+        }
+    }
+
+    pub fn buffer<T>(&mut self, content: &[T]) {
+        self.set_size(std::mem::size_of_val(content));
         unsafe {
             gl::BufferData(
                 self.buffertype,
@@ -67,11 +152,557 @@ impl Buffer {
             );
         }
     }
+
+    /// Copies buffer contents back to the cpu, the buffer must be bound.
+    pub fn download<T>(&self, content: &mut [T], offset: usize) {
+        unsafe {
+            gl::GetBufferSubData(
+                self.buffertype,
+                offset as isize,
+                std::mem::size_of_val(content) as isize,
+                content.as_mut_ptr() as *mut std::ffi::c_void,
+            );
+        }
+    }
+
+    /// Size in bytes of the current storage.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the storage was made with `storage`.
+    pub fn is_immutable(&self) -> bool {
+        self.immutable
+    }
+
+    // Reallocating takes &mut self, so it cannot happen while a
+    // MappedRange borrows the buffer.
+    fn set_size(&mut self, size: usize) {
+        assert!(
+            !self.immutable,
+            "[ERROR] Cannot reallocate a buffer made with storage()"
+        );
+        self.size = size;
+    }
+
+    /// Immutable storage (glBufferStorage, GL 4.4+), needed for
+    /// persistent mapping. After this the buffer can no longer be
+    /// reallocated with `init`, `buffer`, `orphan` or `storage`, only
+    /// updated. `content` must be exactly `size` bytes.
+    pub fn storage<T>(
+        &mut self,
+        size: usize,
+        content: Option<&[T]>,
+        flags: StorageFlags,
+    ) -> Result<(), &'static str> {
+        if self.immutable {
+            return Err("The buffer already has immutable storage");
+        }
+        if crate::utils::gl::version() < (4, 4) {
+            return Err("Immutable buffer storage needs GL 4.4");
+        }
+        if let Some(content) = content {
+            if std::mem::size_of_val(content) != size {
+                return Err("Content is not the size of the storage");
+            }
+        }
+        self.set_size(size);
+        self.immutable = true;
+        unsafe {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.id);
+            gl::BufferStorage(
+                gl::COPY_WRITE_BUFFER,
+                size as isize,
+                content.map_or(std::ptr::null(), |c| c.as_ptr() as *const std::ffi::c_void),
+                flags.0,
+            );
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+        }
+        Ok(())
+    }
+
+    /// Maps `len` T's starting at byte `offset`, a multiple of the
+    /// alignment of T, unmapped when the guard drops. Uses the copy
+    /// write target, so bindings (and the index buffer of a bound
+    /// VertexArray) are left alone.
+    ///
+    /// Only ranges mapped with `READ` and without `INVALIDATE_*` hold
+    /// defined data and can be used as a slice, write the others
+    /// with `MappedRange::write`.
+    pub fn map_range<T: Pod>(
+        &self,
+        offset: usize,
+        len: usize,
+        flags: MapFlags,
+    ) -> Result<MappedRange<'_, T>, &'static str> {
+        if offset & (std::mem::align_of::<T>() - 1) != 0 {
+            return Err("Mapped range offset is not aligned for the type");
+        }
+        let n_bytes = len
+            .checked_mul(std::mem::size_of::<T>())
+            .ok_or("Mapped range exceeds the buffer size")?;
+        let ptr = self.map_raw(offset, n_bytes, flags)? as *mut T;
+        let invalidate = MapFlags::INVALIDATE_RANGE.0 | MapFlags::INVALIDATE_BUFFER.0;
+        Ok(MappedRange {
+            buffer: self,
+            ptr,
+            len,
+            readable: flags.0 & gl::MAP_READ_BIT != 0 && flags.0 & invalidate == 0,
+        })
+    }
+
+    fn map_raw(
+        &self,
+        offset: usize,
+        n_bytes: usize,
+        flags: MapFlags,
+    ) -> Result<*mut std::ffi::c_void, &'static str> {
+        if !matches!(offset.checked_add(n_bytes), Some(end) if end <= self.size) {
+            return Err("Mapped range exceeds the buffer size");
+        }
+        if n_bytes == 0 {
+            return Err("Cannot map an empty range");
+        }
+        let ptr = unsafe {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.id);
+            let ptr = gl::MapBufferRange(
+                gl::COPY_WRITE_BUFFER,
+                offset as isize,
+                n_bytes as isize,
+                flags.0,
+            );
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+            ptr
+        };
+        if ptr.is_null() {
+            return Err("glMapBufferRange failed");
+        }
+        Ok(ptr)
+    }
+
+    fn unmap(&self) {
+        unsafe {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.id);
+            gl::UnmapBuffer(gl::COPY_WRITE_BUFFER);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+        }
+    }
+
+    /// Copies `size` bytes on the gpu, without going through the cpu.
+    pub fn copy_to(
+        &self,
+        dst: &Buffer,
+        src_offset: usize,
+        dst_offset: usize,
+        size: usize,
+    ) -> Result<(), &'static str> {
+        let fits = |offset: usize, buffer: &Buffer| match offset.checked_add(size) {
+            Some(end) => end <= buffer.size(),
+            None => false,
+        };
+        if !fits(src_offset, self) || !fits(dst_offset, dst) {
+            return Err("Buffer copy out of bounds");
+        }
+        unsafe {
+            gl::BindBuffer(gl::COPY_READ_BUFFER, self.id);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, dst.id);
+            gl::CopyBufferSubData(
+                gl::COPY_READ_BUFFER,
+                gl::COPY_WRITE_BUFFER,
+                src_offset as isize,
+                dst_offset as isize,
+                size as isize,
+            );
+            gl::BindBuffer(gl::COPY_READ_BUFFER, 0);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+        }
+        Ok(())
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// A buffer that knows it holds T's. Keeps track of how many
+/// (`len`) and how many fit (`capacity`), offsets are in T's.
+///
+/// All uploads go through the copy write target, so the buffer does
+/// not have to be bound and a bound VertexArray is left untouched.
+/// Growing keeps the same GL buffer, so attribute pointers stay valid.
+pub struct TypedBuffer<T> {
+    buffer: Buffer,
+    len: usize,
+    capacity: usize,
+    _type: PhantomData<T>,
+}
+
+impl<T: Copy> TypedBuffer<T> {
+    pub fn new(buffertype: BufferType, drawtype: DrawType) -> Self {
+        Self::with_capacity(buffertype, drawtype, 0)
+    }
+
+    pub fn with_capacity(buffertype: BufferType, drawtype: DrawType, capacity: usize) -> Self {
+        let mut this = Self {
+            buffer: Buffer::new(buffertype, drawtype),
+            len: 0,
+            capacity: 0,
+            _type: PhantomData,
+        };
+        this.reallocate(capacity, false);
+        this
+    }
+
+    pub fn from_slice(buffertype: BufferType, drawtype: DrawType, content: &[T]) -> Self {
+        let mut this = Self::new(buffertype, drawtype);
+        this.set(content);
+        this
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// Replaces all contents, reallocating only when they do not fit.
+    pub fn set(&mut self, content: &[T]) {
+        if content.len() > self.capacity {
+            self.reallocate(content.len(), false);
+        }
+        self.write(0, content);
+        self.len = content.len();
+    }
+
+    /// Appends, at least doubling the capacity when it is full.
+    pub fn push(&mut self, content: &[T]) {
+        let needed = self.len + content.len();
+        if needed > self.capacity {
+            self.reallocate(needed.max(self.capacity * 2), true);
+        }
+        self.write(self.len, content);
+        self.len = needed;
+    }
+
+    /// Overwrites T's starting at element `offset`, which
+    /// must lie within the current `len`.
+    pub fn update(&mut self, offset: usize, content: &[T]) -> Result<(), &'static str> {
+        if !matches!(offset.checked_add(content.len()), Some(end) if end <= self.len) {
+            return Err("Update out of the bounds of the typed buffer");
+        }
+        self.write(offset, content);
+        Ok(())
+    }
+
+    /// Forgets the contents, but keeps the storage.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    /// Makes room for at least `capacity` T's, keeping the contents.
+    pub fn reserve(&mut self, capacity: usize) {
+        if capacity > self.capacity {
+            self.reallocate(capacity, true);
+        }
+    }
+
+    /// Uploads `content` to element `offset` with glBufferSubData,
+    /// it must fit in the capacity.
+    fn write(&self, offset: usize, content: &[T]) {
+        if content.is_empty() {
+            return;
+        }
+        unsafe {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.buffer.id);
+            gl::BufferSubData(
+                gl::COPY_WRITE_BUFFER,
+                (offset * std::mem::size_of::<T>()) as isize,
+                std::mem::size_of_val(content) as isize,
+                content.as_ptr() as *const std::ffi::c_void,
+            );
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+        }
+    }
+
+    // Reallocates the same GL buffer, optionally keeping the
+    // contents by copying them through a temporary buffer.
+    fn reallocate(&mut self, capacity: usize, keep: bool) {
+        let n_bytes = capacity * std::mem::size_of::<T>();
+        let used = self.len * std::mem::size_of::<T>();
+        let temp = if keep && used > 0 {
+            let mut temp = Buffer::new(BufferType::Vertex, DrawType::Static);
+            unsafe {
+                gl::BindBuffer(gl::COPY_WRITE_BUFFER, temp.id);
+                gl::BufferData(
+                    gl::COPY_WRITE_BUFFER,
+                    used as isize,
+                    std::ptr::null(),
+                    gl::STREAM_COPY,
+                );
+            }
+            temp.size = used;
+            self.buffer
+                .copy_to(&temp, 0, 0, used)
+                .expect("[ERROR] The used part fits both buffers");
+            Some(temp)
+        } else {
+            None
+        };
+        self.buffer.set_size(n_bytes);
+        unsafe {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.buffer.id);
+            gl::BufferData(
+                gl::COPY_WRITE_BUFFER,
+                n_bytes as isize,
+                std::ptr::null(),
+                self.buffer.drawtype,
+            );
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+        }
+        if let Some(temp) = temp {
+            temp.copy_to(&self.buffer, 0, 0, used)
+                .expect("[ERROR] The used part fits both buffers");
+        } else {
+            self.len = 0;
+        }
+        self.capacity = capacity;
+    }
+}
+
+impl<T: Copy + Pod> TypedBuffer<T> {
+    /// Maps `len` T's from element `offset`, within the current `len`.
+    pub fn map_range(
+        &self,
+        offset: usize,
+        len: usize,
+        flags: MapFlags,
+    ) -> Result<MappedRange<'_, T>, &'static str> {
+        if !matches!(offset.checked_add(len), Some(end) if end <= self.len) {
+            return Err("Mapped range out of the bounds of the typed buffer");
+        }
+        self.buffer
+            .map_range(offset * std::mem::size_of::<T>(), len, flags)
+    }
+}
+
+/// A mapped range of a `Buffer`. Readable ranges can be used as a
+/// slice, write-only ones only through `write`, as their memory is
+/// undefined until written.
+pub struct MappedRange<'a, T: Pod> {
+    buffer: &'a Buffer,
+    ptr: *mut T,
+    len: usize,
+    readable: bool,
+}
+
+impl<'a, T: Pod> MappedRange<'a, T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copies `content` to element `offset` of the range.
+    pub fn write(&mut self, offset: usize, content: &[T]) {
+        assert!(
+            offset <= self.len && content.len() <= self.len - offset,
+            "[ERROR] Write out of the bounds of the mapped range"
+        );
+        unsafe {
+            std::ptr::copy_nonoverlapping(content.as_ptr(), self.ptr.add(offset), content.len());
+        }
+    }
+
+    /// Only for ranges mapped with `MapFlags::FLUSH_EXPLICIT`,
+    /// `offset` and `len` in T's relative to the mapped range.
+    pub fn flush(&self, offset: usize, len: usize) {
+        flush_mapped(self.buffer.id, offset, len, std::mem::size_of::<T>());
+    }
+
+    fn check_readable(&self) {
+        assert!(
+            self.readable,
+            "[ERROR] Range is mapped without READ or with INVALIDATE, use write()"
+        );
+    }
+}
+
+impl<'a, T: Pod> Deref for MappedRange<'a, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        self.check_readable();
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<'a, T: Pod> DerefMut for MappedRange<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.check_readable();
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<'a, T: Pod> Drop for MappedRange<'a, T> {
+    fn drop(&mut self) {
+        self.buffer.unmap();
+    }
+}
+
+/// A buffer with immutable storage that stays mapped for its whole
+/// life (GL 4.4+). The gpu may read the memory while it is written,
+/// synchronize with fences, f.i. see `sync::Fence`. The mapping is
+/// write-only, so it is only written through `write`.
+pub struct PersistentBuffer<T: Pod> {
+    buffer: Buffer,
+    ptr: *mut T,
+    len: usize,
+    coherent: bool,
+}
+
+impl<T: Pod> PersistentBuffer<T> {
+    /// Room for `len` T's, zeroed. Without `coherent`, writes become
+    /// visible to the gpu only after `flush`.
+    pub fn new(buffertype: BufferType, len: usize, coherent: bool) -> Result<Self, &'static str> {
+        let mut buffer = Buffer::new(buffertype, DrawType::Dynamic);
+        let n_bytes = len
+            .checked_mul(std::mem::size_of::<T>())
+            .ok_or("Persistent buffer is too large")?;
+        let mut storage = StorageFlags::MAP_WRITE | StorageFlags::PERSISTENT;
+        let mut map = MapFlags::WRITE | MapFlags::PERSISTENT;
+        if coherent {
+            storage = storage | StorageFlags::COHERENT;
+            map = map | MapFlags::COHERENT;
+        } else {
+            map = map | MapFlags::FLUSH_EXPLICIT;
+        }
+        // zeroed, so ranges never written hold no garbage
+        buffer.storage(n_bytes, Some(&vec![0u8; n_bytes]), storage)?;
+        let ptr = buffer.map_raw(0, n_bytes, map)? as *mut T;
+        Ok(Self {
+            buffer,
+            ptr,
+            len,
+            coherent,
+        })
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copies `content` to element `offset`.
+    pub fn write(&mut self, offset: usize, content: &[T]) {
+        assert!(
+            offset <= self.len && content.len() <= self.len - offset,
+            "[ERROR] Write out of the bounds of the persistent buffer"
+        );
+        unsafe {
+            std::ptr::copy_nonoverlapping(content.as_ptr(), self.ptr.add(offset), content.len());
+        }
+    }
+
+    /// Makes writes to `len` T's from `offset` visible, a no-op for
+    /// coherent buffers.
+    pub fn flush(&self, offset: usize, len: usize) {
+        if !self.coherent {
+            flush_mapped(self.buffer.id, offset, len, std::mem::size_of::<T>());
+        }
+    }
+}
+
+impl<T: Pod> Drop for PersistentBuffer<T> {
+    fn drop(&mut self) {
+        self.buffer.unmap();
+    }
+}
+
+fn flush_mapped(id: u32, offset: usize, len: usize, elem_size: usize) {
+    unsafe {
+        gl::BindBuffer(gl::COPY_WRITE_BUFFER, id);
+        gl::FlushMappedBufferRange(
+            gl::COPY_WRITE_BUFFER,
+            (offset * elem_size) as isize,
+            (len * elem_size) as isize,
+        );
+        gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+    }
+}
+
+/// Access flags of `Buffer::map_range`, combine with `|`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MapFlags(u32);
+
+impl MapFlags {
+    pub const READ: Self = Self(gl::MAP_READ_BIT);
+    pub const WRITE: Self = Self(gl::MAP_WRITE_BIT);
+    /// the old contents of the range may be discarded
+    pub const INVALIDATE_RANGE: Self = Self(gl::MAP_INVALIDATE_RANGE_BIT);
+    /// the old contents of the whole buffer may be discarded
+    pub const INVALIDATE_BUFFER: Self = Self(gl::MAP_INVALIDATE_BUFFER_BIT);
+    pub const FLUSH_EXPLICIT: Self = Self(gl::MAP_FLUSH_EXPLICIT_BIT);
+    /// do not wait for the gpu to finish using the range
+    pub const UNSYNCHRONIZED: Self = Self(gl::MAP_UNSYNCHRONIZED_BIT);
+    pub const PERSISTENT: Self = Self(gl::MAP_PERSISTENT_BIT);
+    pub const COHERENT: Self = Self(gl::MAP_COHERENT_BIT);
+}
+
+impl BitOr for MapFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Flags of `Buffer::storage`, combine with `|`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StorageFlags(u32);
+
+impl StorageFlags {
+    pub const NONE: Self = Self(0);
+    /// allows `subbuffer` on the immutable storage
+    pub const DYNAMIC: Self = Self(gl::DYNAMIC_STORAGE_BIT);
+    pub const MAP_READ: Self = Self(gl::MAP_READ_BIT);
+    pub const MAP_WRITE: Self = Self(gl::MAP_WRITE_BIT);
+    pub const PERSISTENT: Self = Self(gl::MAP_PERSISTENT_BIT);
+    pub const COHERENT: Self = Self(gl::MAP_COHERENT_BIT);
+    pub const CLIENT: Self = Self(gl::CLIENT_STORAGE_BIT);
+}
+
+impl BitOr for StorageFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
 }
 
 impl Drop for Buffer {
     fn drop(&mut self) {
         unsafe { gl::DeleteBuffers(1, &self.id) }
+        state::deleted_buffer(self.id);
     }
 }
 
@@ -81,11 +712,33 @@ pub enum BufferType {
     Index = gl::ELEMENT_ARRAY_BUFFER,
     Vertex = gl::ARRAY_BUFFER,
     Uniform = gl::UNIFORM_BUFFER,
+    /// Destination of glReadPixels, for async readback
+    PixelPack = gl::PIXEL_PACK_BUFFER,
+    /// Source of glTexSubImage2D, for async uploads
+    PixelUnpack = gl::PIXEL_UNPACK_BUFFER,
+    /// layout (std430, binding=n) buffer { ... }, GL 4.3
+    ShaderStorage = gl::SHADER_STORAGE_BUFFER,
+    /// layout (binding=n) uniform atomic_uint, GL 4.2
+    AtomicCounter = gl::ATOMIC_COUNTER_BUFFER,
+    /// Storage of a `BufferTexture`, a samplerBuffer in glsl
+    Texture = gl::TEXTURE_BUFFER,
+    TransformFeedback = gl::TRANSFORM_FEEDBACK_BUFFER,
 }
 
+/// Usage hints, how often the contents change (static: once,
+/// dynamic: now and then, stream: about every use) and who reads
+/// them (draw: the gpu, read: the cpu, copy: the gpu, written by
+/// the gpu). The draw variants have the short names.
 #[repr(u32)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DrawType {
     Static = gl::STATIC_DRAW,
+    StaticRead = gl::STATIC_READ,
+    StaticCopy = gl::STATIC_COPY,
     Dynamic = gl::DYNAMIC_DRAW,
+    DynamicRead = gl::DYNAMIC_READ,
+    DynamicCopy = gl::DYNAMIC_COPY,
+    Stream = gl::STREAM_DRAW,
+    StreamRead = gl::STREAM_READ,
+    StreamCopy = gl::STREAM_COPY,
 }
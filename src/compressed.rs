@@ -0,0 +1,784 @@
+use std::path::Path;
+
+/// A GPU compressed image with its full mip chain, as read
+/// from a KTX (v1/v2) or DDS container. Level 0 is the largest.
+///
+/// Note: the blocks are kept in file order (top row first), they
+///       are not flipped like `Texture::from_path` does with
+///       plain images, so flip the v coordinate when sampling.
+pub struct CompressedImage {
+    pub format: CompressedFormat,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, String> {
+        let bytes = std::fs::read(&path).map_err(|e| {
+            format!(
+                "Could not read compressed image from path '{:?}': {}",
+                path.as_ref(),
+                e
+            )
+        })?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Detects the container from its magic bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.starts_with(&KTX1_IDENTIFIER) {
+            Self::from_ktx1(bytes)
+        } else if bytes.starts_with(&KTX2_IDENTIFIER) {
+            Self::from_ktx2(bytes)
+        } else if bytes.starts_with(DDS_MAGIC) {
+            Self::from_dds(bytes)
+        } else {
+            Err("Unknown compressed image container, expected KTX, KTX2 or DDS".into())
+        }
+    }
+
+    pub fn from_ktx1(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.starts_with(&KTX1_IDENTIFIER) {
+            return Err("Not a KTX file".into());
+        }
+        let big_endian = match read_u32(bytes, 12, false)? {
+            0x04030201 => false,
+            0x01020304 => true,
+            _ => return Err("KTX file has an invalid endianness field".into()),
+        };
+        let field = |i: usize| read_u32(bytes, 16 + i * 4, big_endian);
+        let gl_type = field(0)?;
+        let internal_format = field(3)?;
+        let width = field(5)?;
+        let height = field(6)?;
+        let depth = field(7)?;
+        let array_elements = field(8)?;
+        let faces = field(9)?;
+        let n_levels = check_levels(field(10)?)?;
+        let kv_bytes = field(11)? as usize;
+
+        if gl_type != 0 {
+            return Err("KTX file does not contain compressed data".into());
+        }
+        if depth > 1 || array_elements > 1 || faces != 1 {
+            return Err("Only 2D KTX textures are supported".into());
+        }
+        let format = CompressedFormat::from_gl(internal_format).ok_or_else(|| {
+            format!(
+                "KTX internal format {:#x} is not a supported compressed format",
+                internal_format
+            )
+        })?;
+
+        let mut offset = add(64, kv_bytes)?;
+        let mut levels = Vec::with_capacity(n_levels as usize);
+        for _ in 0..n_levels {
+            let size = read_u32(bytes, offset, big_endian)? as usize;
+            offset = add(offset, 4)?;
+            levels.push(slice(bytes, offset, size)?.to_vec());
+            offset = add(offset, add(size, 3)? & !3)?; // mip padding
+        }
+
+        Self::new(format, width, height, levels)
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.starts_with(&KTX2_IDENTIFIER) {
+            return Err("Not a KTX2 file".into());
+        }
+        let field = |i: usize| read_u32(bytes, 12 + i * 4, false);
+        let vk_format = field(0)?;
+        let width = field(2)?;
+        let height = field(3)?;
+        let depth = field(4)?;
+        let layers = field(5)?;
+        let faces = field(6)?;
+        let n_levels = check_levels(field(7)?)?;
+        let supercompression = field(8)?;
+
+        if supercompression != 0 {
+            return Err("Supercompressed KTX2 files are not supported".into());
+        }
+        if depth > 1 || layers > 1 || faces != 1 {
+            return Err("Only 2D KTX2 textures are supported".into());
+        }
+        let format = CompressedFormat::from_vk(vk_format).ok_or_else(|| {
+            format!(
+                "KTX2 vkFormat {} is not a supported compressed format",
+                vk_format
+            )
+        })?;
+
+        // the level index directly follows the 80 byte header
+        let mut levels = Vec::with_capacity(n_levels as usize);
+        for i in 0..n_levels as usize {
+            let entry = 80 + i * 24;
+            let offset = to_usize(read_u64(bytes, entry)?)?;
+            let size = to_usize(read_u64(bytes, entry + 8)?)?;
+            levels.push(slice(bytes, offset, size)?.to_vec());
+        }
+
+        Self::new(format, width, height, levels)
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.starts_with(DDS_MAGIC) {
+            return Err("Not a DDS file".into());
+        }
+        if read_u32(bytes, 4, false)? != 124 {
+            return Err("DDS file has an invalid header size".into());
+        }
+        let height = read_u32(bytes, 12, false)?;
+        let width = read_u32(bytes, 16, false)?;
+        let n_levels = check_levels(read_u32(bytes, 28, false)?)?;
+        let pf_flags = read_u32(bytes, 80, false)?;
+        let four_cc = slice(bytes, 84, 4)?;
+        let caps2 = read_u32(bytes, 112, false)?;
+
+        if caps2 & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+            return Err("Only 2D DDS textures are supported".into());
+        }
+        if pf_flags & DDPF_FOURCC == 0 {
+            return Err("DDS file does not contain compressed data".into());
+        }
+
+        let mut offset = 128;
+        let format = match four_cc {
+            b"DXT1" => Some(CompressedFormat::Bc1 {
+                alpha: true,
+                srgb: false,
+            }),
+            b"DXT3" => Some(CompressedFormat::Bc2 { srgb: false }),
+            b"DXT5" => Some(CompressedFormat::Bc3 { srgb: false }),
+            b"ATI1" | b"BC4U" => Some(CompressedFormat::Bc4 { signed: false }),
+            b"BC4S" => Some(CompressedFormat::Bc4 { signed: true }),
+            b"ATI2" | b"BC5U" => Some(CompressedFormat::Bc5 { signed: false }),
+            b"BC5S" => Some(CompressedFormat::Bc5 { signed: true }),
+            b"DX10" => {
+                let dxgi_format = read_u32(bytes, 128, false)?;
+                let dimension = read_u32(bytes, 132, false)?;
+                let array_size = read_u32(bytes, 140, false)?;
+                if dimension != DDS_DIMENSION_TEXTURE2D || array_size > 1 {
+                    return Err("Only 2D DDS textures are supported".into());
+                }
+                offset += 20;
+                Some(CompressedFormat::from_dxgi(dxgi_format).ok_or_else(|| {
+                    format!(
+                        "DXGI format {} is not a supported compressed format",
+                        dxgi_format
+                    )
+                })?)
+            }
+            _ => None,
+        }
+        .ok_or_else(|| {
+            format!(
+                "DDS FourCC {:?} is not a supported compressed format",
+                String::from_utf8_lossy(four_cc)
+            )
+        })?;
+
+        // dds does not store level sizes, they are tightly packed
+        let mut levels = Vec::with_capacity(n_levels as usize);
+        for level in 0..n_levels {
+            let (w, h) = level_dims(width, height, level);
+            let size = format.level_size(w, h);
+            levels.push(slice(bytes, offset, size)?.to_vec());
+            offset = add(offset, size)?;
+        }
+
+        Self::new(format, width, height, levels)
+    }
+
+    fn new(
+        format: CompressedFormat,
+        width: u32,
+        height: u32,
+        levels: Vec<Vec<u8>>,
+    ) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err("Compressed image has a zero dimension".into());
+        }
+        for (level, data) in levels.iter().enumerate() {
+            let (w, h) = level_dims(width, height, level as u32);
+            let expected = format.level_size(w, h);
+            if data.len() != expected {
+                return Err(format!(
+                    "Mip level {} is {} bytes, expected {} for a {}x{} {:?} image",
+                    level,
+                    data.len(),
+                    expected,
+                    w,
+                    h,
+                    format
+                ));
+            }
+        }
+        Ok(Self {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    pub fn level_dims(&self, level: u32) -> (u32, u32) {
+        level_dims(self.width, self.height, level)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompressedFormat {
+    Bc1 { alpha: bool, srgb: bool },
+    Bc2 { srgb: bool },
+    Bc3 { srgb: bool },
+    Bc4 { signed: bool },
+    Bc5 { signed: bool },
+    Bc6h { signed: bool },
+    Bc7 { srgb: bool },
+    Etc2Rgb { srgb: bool },
+    Etc2RgbA1 { srgb: bool },
+    Etc2Rgba { srgb: bool },
+    EacR11 { signed: bool },
+    EacRg11 { signed: bool },
+    /// block width and height in texels
+    Astc { block: (u8, u8), srgb: bool },
+}
+
+impl CompressedFormat {
+    /// All formats this module knows of, f.i. to check
+    /// which of them the driver supports.
+    pub fn all() -> Vec<Self> {
+        let mut formats = Vec::new();
+        for &b in &[false, true] {
+            formats.push(Self::Bc1 { alpha: false, srgb: b });
+            formats.push(Self::Bc1 { alpha: true, srgb: b });
+            formats.push(Self::Bc2 { srgb: b });
+            formats.push(Self::Bc3 { srgb: b });
+            formats.push(Self::Bc4 { signed: b });
+            formats.push(Self::Bc5 { signed: b });
+            formats.push(Self::Bc6h { signed: b });
+            formats.push(Self::Bc7 { srgb: b });
+            formats.push(Self::Etc2Rgb { srgb: b });
+            formats.push(Self::Etc2RgbA1 { srgb: b });
+            formats.push(Self::Etc2Rgba { srgb: b });
+            formats.push(Self::EacR11 { signed: b });
+            formats.push(Self::EacRg11 { signed: b });
+            for &block in ASTC_BLOCKS.iter() {
+                formats.push(Self::Astc { block, srgb: b });
+            }
+        }
+        formats
+    }
+
+    pub fn block_dims(&self) -> (u32, u32) {
+        match self {
+            Self::Astc { block: (w, h), .. } => (*w as u32, *h as u32),
+            _ => (4, 4),
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        match self {
+            Self::Bc1 { .. }
+            | Self::Bc4 { .. }
+            | Self::Etc2Rgb { .. }
+            | Self::Etc2RgbA1 { .. }
+            | Self::EacR11 { .. } => 8,
+            _ => 16,
+        }
+    }
+
+    /// Size in bytes of a single mip level of the given dimensions,
+    /// saturated at usize::MAX for absurd dimensions.
+    pub fn level_size(&self, width: u32, height: u32) -> usize {
+        let (bw, bh) = self.block_dims();
+        let blocks_x = width.div_ceil(bw) as usize;
+        let blocks_y = height.div_ceil(bh) as usize;
+        blocks_x
+            .saturating_mul(blocks_y)
+            .saturating_mul(self.block_size())
+    }
+
+    /// The internalformat passed to glCompressedTexImage2D.
+    pub fn gl(&self) -> u32 {
+        use CompressedFormat::*;
+
+        match *self {
+            Bc1 { alpha: false, srgb: false } => COMPRESSED_RGB_S3TC_DXT1,
+            Bc1 { alpha: true, srgb: false } => COMPRESSED_RGBA_S3TC_DXT1,
+            Bc1 { alpha: false, srgb: true } => COMPRESSED_SRGB_S3TC_DXT1,
+            Bc1 { alpha: true, srgb: true } => COMPRESSED_SRGB_ALPHA_S3TC_DXT1,
+            Bc2 { srgb: false } => COMPRESSED_RGBA_S3TC_DXT3,
+            Bc2 { srgb: true } => COMPRESSED_SRGB_ALPHA_S3TC_DXT3,
+            Bc3 { srgb: false } => COMPRESSED_RGBA_S3TC_DXT5,
+            Bc3 { srgb: true } => COMPRESSED_SRGB_ALPHA_S3TC_DXT5,
+            Bc4 { signed: false } => gl::COMPRESSED_RED_RGTC1,
+            Bc4 { signed: true } => gl::COMPRESSED_SIGNED_RED_RGTC1,
+            Bc5 { signed: false } => gl::COMPRESSED_RG_RGTC2,
+            Bc5 { signed: true } => gl::COMPRESSED_SIGNED_RG_RGTC2,
+            Bc6h { signed: false } => gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT,
+            Bc6h { signed: true } => gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT,
+            Bc7 { srgb: false } => gl::COMPRESSED_RGBA_BPTC_UNORM,
+            Bc7 { srgb: true } => gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM,
+            Etc2Rgb { srgb: false } => gl::COMPRESSED_RGB8_ETC2,
+            Etc2Rgb { srgb: true } => gl::COMPRESSED_SRGB8_ETC2,
+            Etc2RgbA1 { srgb: false } => gl::COMPRESSED_RGB8_PUNCHTHROUGH_ALPHA1_ETC2,
+            Etc2RgbA1 { srgb: true } => gl::COMPRESSED_SRGB8_PUNCHTHROUGH_ALPHA1_ETC2,
+            Etc2Rgba { srgb: false } => gl::COMPRESSED_RGBA8_ETC2_EAC,
+            Etc2Rgba { srgb: true } => gl::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC,
+            EacR11 { signed: false } => gl::COMPRESSED_R11_EAC,
+            EacR11 { signed: true } => gl::COMPRESSED_SIGNED_R11_EAC,
+            EacRg11 { signed: false } => gl::COMPRESSED_RG11_EAC,
+            EacRg11 { signed: true } => gl::COMPRESSED_SIGNED_RG11_EAC,
+            Astc { block, srgb } => {
+                let i = ASTC_BLOCKS
+                    .iter()
+                    .position(|b| *b == block)
+                    .expect("[ERROR] Invalid ASTC block size") as u32;
+                if srgb {
+                    COMPRESSED_SRGB8_ALPHA8_ASTC_4X4 + i
+                } else {
+                    COMPRESSED_RGBA_ASTC_4X4 + i
+                }
+            }
+        }
+    }
+
+    pub fn from_gl(internal_format: u32) -> Option<Self> {
+        Self::all().into_iter().find(|f| f.gl() == internal_format)
+    }
+
+    /// From the VkFormat stored in KTX2 files.
+    pub fn from_vk(vk_format: u32) -> Option<Self> {
+        use CompressedFormat::*;
+
+        let srgb = vk_format & 1 == 0;
+        let signed = srgb;
+        let format = match vk_format {
+            131 | 132 => Bc1 { alpha: false, srgb },
+            133 | 134 => Bc1 { alpha: true, srgb },
+            135 | 136 => Bc2 { srgb },
+            137 | 138 => Bc3 { srgb },
+            139 | 140 => Bc4 { signed },
+            141 | 142 => Bc5 { signed },
+            143 | 144 => Bc6h { signed },
+            145 | 146 => Bc7 { srgb },
+            147 | 148 => Etc2Rgb { srgb },
+            149 | 150 => Etc2RgbA1 { srgb },
+            151 | 152 => Etc2Rgba { srgb },
+            153 | 154 => EacR11 { signed },
+            155 | 156 => EacRg11 { signed },
+            157..=184 => Astc {
+                block: ASTC_BLOCKS[(vk_format - 157) as usize / 2],
+                srgb,
+            },
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// From the DXGI_FORMAT stored in the DX10 DDS header.
+    pub fn from_dxgi(dxgi_format: u32) -> Option<Self> {
+        use CompressedFormat::*;
+
+        let format = match dxgi_format {
+            70 | 71 => Bc1 { alpha: true, srgb: false },
+            72 => Bc1 { alpha: true, srgb: true },
+            73 | 74 => Bc2 { srgb: false },
+            75 => Bc2 { srgb: true },
+            76 | 77 => Bc3 { srgb: false },
+            78 => Bc3 { srgb: true },
+            79 | 80 => Bc4 { signed: false },
+            81 => Bc4 { signed: true },
+            82 | 83 => Bc5 { signed: false },
+            84 => Bc5 { signed: true },
+            94 | 95 => Bc6h { signed: false },
+            96 => Bc6h { signed: true },
+            97 | 98 => Bc7 { srgb: false },
+            99 => Bc7 { srgb: true },
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Whether the current context can upload this format. Decided
+    /// from the GL version and extensions, GL_COMPRESSED_TEXTURE_FORMATS
+    /// only has to list general purpose formats and drivers leave out
+    /// f.i. RGTC and BPTC although they are core.
+    pub fn is_supported(&self) -> bool {
+        use crate::utils::gl::{has_extension, version};
+        use CompressedFormat::*;
+
+        match *self {
+            Bc1 { srgb, .. } | Bc2 { srgb } | Bc3 { srgb } => {
+                has_extension("GL_EXT_texture_compression_s3tc")
+                    && (!srgb
+                        || has_extension("GL_EXT_texture_sRGB")
+                        || has_extension("GL_EXT_texture_compression_s3tc_srgb"))
+            }
+            // RGTC is core since GL 3.0
+            Bc4 { .. } | Bc5 { .. } => true,
+            Bc6h { .. } | Bc7 { .. } => {
+                version() >= (4, 2) || has_extension("GL_ARB_texture_compression_bptc")
+            }
+            Etc2Rgb { .. }
+            | Etc2RgbA1 { .. }
+            | Etc2Rgba { .. }
+            | EacR11 { .. }
+            | EacRg11 { .. } => version() >= (4, 3) || has_extension("GL_ARB_ES3_compatibility"),
+            Astc { .. } => has_extension("GL_KHR_texture_compression_astc_ldr"),
+        }
+    }
+}
+
+pub fn level_dims(width: u32, height: u32, level: u32) -> (u32, u32) {
+    let dim = |d: u32| d.checked_shr(level).unwrap_or(0).max(1);
+    (dim(width), dim(height))
+}
+
+// a 32 bit dimension has at most 32 levels, more is a corrupt header
+// and must not be used to allocate
+fn check_levels(n_levels: u32) -> Result<u32, String> {
+    if n_levels > 32 {
+        return Err(format!(
+            "Compressed image claims {} mip levels, at most 32 are possible",
+            n_levels
+        ));
+    }
+    Ok(n_levels.max(1))
+}
+
+fn add(a: usize, b: usize) -> Result<usize, String> {
+    a.checked_add(b)
+        .ok_or_else(|| "Compressed image has an out of range offset".to_string())
+}
+
+fn to_usize(n: u64) -> Result<usize, String> {
+    use std::convert::TryFrom;
+    usize::try_from(n).map_err(|_| "Compressed image has an out of range offset".to_string())
+}
+
+fn slice(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8], String> {
+    offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| format!("Compressed image is truncated at byte {}", offset))
+}
+
+fn read_u32(bytes: &[u8], offset: usize, big_endian: bool) -> Result<u32, String> {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(slice(bytes, offset, 4)?);
+    Ok(if big_endian {
+        u32::from_be_bytes(b)
+    } else {
+        u32::from_le_bytes(b)
+    })
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, String> {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(slice(bytes, offset, 8)?);
+    Ok(u64::from_le_bytes(b))
+}
+
+const KTX1_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x31, 0x31, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const DDS_MAGIC: &[u8] = b"DDS ";
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const DDS_DIMENSION_TEXTURE2D: u32 = 3;
+
+// ordered like the GL and Vulkan enums
+const ASTC_BLOCKS: [(u8, u8); 14] = [
+    (4, 4),
+    (5, 4),
+    (5, 5),
+    (6, 5),
+    (6, 6),
+    (8, 5),
+    (8, 6),
+    (8, 8),
+    (10, 5),
+    (10, 6),
+    (10, 8),
+    (10, 10),
+    (12, 10),
+    (12, 12),
+];
+
+// Extension enums, these are not in the core profile bindings.
+// EXT_texture_compression_s3tc and EXT_texture_sRGB
+const COMPRESSED_RGB_S3TC_DXT1: u32 = 0x83F0;
+const COMPRESSED_RGBA_S3TC_DXT1: u32 = 0x83F1;
+const COMPRESSED_RGBA_S3TC_DXT3: u32 = 0x83F2;
+const COMPRESSED_RGBA_S3TC_DXT5: u32 = 0x83F3;
+const COMPRESSED_SRGB_S3TC_DXT1: u32 = 0x8C4C;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT1: u32 = 0x8C4D;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT3: u32 = 0x8C4E;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT5: u32 = 0x8C4F;
+// KHR_texture_compression_astc_ldr, the other block sizes follow
+const COMPRESSED_RGBA_ASTC_4X4: u32 = 0x93B0;
+const COMPRESSED_SRGB8_ALPHA8_ASTC_4X4: u32 = 0x93D0;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_u32(bytes: &mut Vec<u8>, v: u32, big_endian: bool) {
+        if big_endian {
+            bytes.extend_from_slice(&v.to_be_bytes());
+        } else {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    // a level filled with its own index, to check which bytes went where
+    fn level(format: CompressedFormat, w: u32, h: u32, i: u8) -> Vec<u8> {
+        vec![i; format.level_size(w, h)]
+    }
+
+    fn ktx1(
+        internal_format: u32,
+        w: u32,
+        h: u32,
+        levels: &[Vec<u8>],
+        kv: &[u8],
+        be: bool,
+    ) -> Vec<u8> {
+        let mut bytes = KTX1_IDENTIFIER.to_vec();
+        push_u32(&mut bytes, 0x04030201, be);
+        let fields = [
+            0,
+            1,
+            0,
+            internal_format,
+            0,
+            w,
+            h,
+            0,
+            0,
+            1,
+            levels.len() as u32,
+            kv.len() as u32,
+        ];
+        for &f in fields.iter() {
+            push_u32(&mut bytes, f, be);
+        }
+        bytes.extend_from_slice(kv);
+        for level in levels {
+            push_u32(&mut bytes, level.len() as u32, be);
+            bytes.extend_from_slice(level);
+        }
+        bytes
+    }
+
+    // levels are stored back to front after a gap, to check that the
+    // offsets from the level index are used
+    fn ktx2(vk_format: u32, w: u32, h: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for &f in [vk_format, 1, w, h, 0, 0, 1, levels.len() as u32, 0].iter() {
+            push_u32(&mut bytes, f, false);
+        }
+        bytes.resize(80, 0);
+        let mut offset = 80 + levels.len() * 24 + 16;
+        let mut data = vec![0xEE; 16];
+        let mut index = Vec::new();
+        for level in levels.iter().rev() {
+            index.push((offset, level.len()));
+            data.extend_from_slice(level);
+            offset += level.len();
+        }
+        for &(offset, len) in index.iter().rev() {
+            bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+            bytes.extend_from_slice(&(len as u64).to_le_bytes());
+            bytes.extend_from_slice(&(len as u64).to_le_bytes());
+        }
+        bytes.extend_from_slice(&data);
+        bytes
+    }
+
+    fn dds(four_cc: &[u8; 4], w: u32, h: u32, n_levels: u32, dxgi: Option<u32>) -> Vec<u8> {
+        let mut bytes = DDS_MAGIC.to_vec();
+        bytes.resize(128, 0);
+        bytes[4..8].copy_from_slice(&124u32.to_le_bytes());
+        bytes[12..16].copy_from_slice(&h.to_le_bytes());
+        bytes[16..20].copy_from_slice(&w.to_le_bytes());
+        bytes[28..32].copy_from_slice(&n_levels.to_le_bytes());
+        bytes[80..84].copy_from_slice(&DDPF_FOURCC.to_le_bytes());
+        bytes[84..88].copy_from_slice(four_cc);
+        if let Some(dxgi) = dxgi {
+            for &f in [dxgi, DDS_DIMENSION_TEXTURE2D, 0, 1, 0].iter() {
+                push_u32(&mut bytes, f, false);
+            }
+        }
+        bytes
+    }
+
+    const BC1: CompressedFormat = CompressedFormat::Bc1 {
+        alpha: true,
+        srgb: false,
+    };
+
+    fn bc1_chain() -> Vec<Vec<u8>> {
+        (0..4)
+            .map(|i| level(BC1, 8 >> i, 8 >> i, i as u8))
+            .collect()
+    }
+
+    #[test]
+    fn ktx1_mip_chain_after_key_values() {
+        for &be in [false, true].iter() {
+            let bytes = ktx1(COMPRESSED_RGBA_S3TC_DXT1, 8, 8, &bc1_chain(), &[7; 12], be);
+            let image = CompressedImage::from_bytes(&bytes).unwrap();
+            assert_eq!(image.format, BC1);
+            assert_eq!((image.width, image.height), (8, 8));
+            assert_eq!(image.levels, bc1_chain());
+        }
+    }
+
+    #[test]
+    fn ktx1_wrong_level_size() {
+        let mut levels = bc1_chain();
+        levels[1].extend_from_slice(&[1; 8]);
+        let bytes = ktx1(COMPRESSED_RGBA_S3TC_DXT1, 8, 8, &levels, &[], false);
+        assert!(CompressedImage::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn ktx1_truncated() {
+        let bytes = ktx1(COMPRESSED_RGBA_S3TC_DXT1, 8, 8, &bc1_chain(), &[], false);
+        for len in [20, 63, 64, bytes.len() - 1].iter() {
+            let err = CompressedImage::from_bytes(&bytes[..*len]).err().unwrap();
+            assert!(err.contains("truncated"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn ktx2_uses_level_index() {
+        let format = CompressedFormat::Bc7 { srgb: true };
+        let levels = (0..3)
+            .map(|i| level(format, 4 >> i, 4 >> i, i))
+            .collect::<Vec<_>>();
+        let image = CompressedImage::from_bytes(&ktx2(146, 4, 4, &levels)).unwrap();
+        assert_eq!(image.format, format);
+        assert_eq!(image.levels, levels);
+    }
+
+    #[test]
+    fn ktx2_bad_offsets_and_level_counts() {
+        let format = CompressedFormat::Bc7 { srgb: false };
+        let mut bytes = ktx2(145, 4, 4, &[level(format, 4, 4, 0)]);
+        bytes[80..88].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(CompressedImage::from_bytes(&bytes).is_err());
+
+        let mut bytes = ktx2(145, 4, 4, &[level(format, 4, 4, 0)]);
+        bytes[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(CompressedImage::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn dds_four_cc_mip_chain() {
+        let format = CompressedFormat::Bc3 { srgb: false };
+        let mut bytes = dds(b"DXT5", 16, 8, 5, None);
+        let levels = (0..5)
+            .map(|i| level(format, 16 >> i, (8 >> i).max(1), i as u8))
+            .collect::<Vec<_>>();
+        levels.iter().for_each(|l| bytes.extend_from_slice(l));
+
+        let image = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.format, format);
+        assert_eq!((image.width, image.height), (16, 8));
+        assert_eq!(image.levels, levels);
+        assert_eq!(image.level_dims(4), (1, 1));
+    }
+
+    #[test]
+    fn dds_dx10_header() {
+        let format = CompressedFormat::Bc7 { srgb: true };
+        let mut bytes = dds(b"DX10", 4, 4, 1, Some(99));
+        bytes.extend_from_slice(&level(format, 4, 4, 9));
+        let image = CompressedImage::from_bytes(&bytes).unwrap();
+        assert_eq!(image.format, format);
+        assert_eq!(image.levels, vec![level(format, 4, 4, 9)]);
+
+        let bytes = dds(b"DX10", 4, 4, 1, Some(2));
+        assert!(CompressedImage::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn dds_malformed() {
+        let mut bytes = dds(b"DXT1", 8, 8, 4, None);
+        bc1_chain().iter().for_each(|l| bytes.extend_from_slice(l));
+        let err = CompressedImage::from_bytes(&bytes[..bytes.len() - 1])
+            .err()
+            .unwrap();
+        assert!(err.contains("truncated"), "{}", err);
+
+        // more levels than bits in the dimensions
+        assert!(CompressedImage::from_bytes(&dds(b"DXT1", 8, 8, 40, None)).is_err());
+        assert!(CompressedImage::from_bytes(&dds(b"DXT1", 8, 8, u32::MAX, None)).is_err());
+        // huge dimensions do not overflow the level size
+        assert!(CompressedImage::from_bytes(&dds(b"DXT1", u32::MAX, u32::MAX, 32, None)).is_err());
+        assert!(CompressedImage::from_bytes(&dds(b"RGBA", 8, 8, 1, None)).is_err());
+    }
+
+    #[test]
+    fn level_dims_never_zero() {
+        assert_eq!(level_dims(16, 4, 0), (16, 4));
+        assert_eq!(level_dims(16, 4, 3), (2, 1));
+        assert_eq!(level_dims(16, 4, 32), (1, 1));
+        assert_eq!(level_dims(u32::MAX, 1, 200), (1, 1));
+    }
+
+    #[test]
+    fn format_mapping() {
+        for format in CompressedFormat::all() {
+            assert_eq!(CompressedFormat::from_gl(format.gl()), Some(format));
+        }
+        assert_eq!(
+            CompressedFormat::from_vk(131),
+            Some(CompressedFormat::Bc1 {
+                alpha: false,
+                srgb: false
+            })
+        );
+        assert_eq!(
+            CompressedFormat::from_vk(140),
+            Some(CompressedFormat::Bc4 { signed: true })
+        );
+        assert_eq!(
+            CompressedFormat::from_vk(157),
+            Some(CompressedFormat::Astc {
+                block: (4, 4),
+                srgb: false
+            })
+        );
+        assert_eq!(
+            CompressedFormat::from_vk(184),
+            Some(CompressedFormat::Astc {
+                block: (12, 12),
+                srgb: true
+            })
+        );
+        assert_eq!(CompressedFormat::from_vk(185), None);
+        assert_eq!(
+            CompressedFormat::from_dxgi(84),
+            Some(CompressedFormat::Bc5 { signed: true })
+        );
+        assert_eq!(CompressedFormat::from_dxgi(100), None);
+
+        let astc = CompressedFormat::Astc {
+            block: (10, 6),
+            srgb: false,
+        };
+        assert_eq!(astc.level_size(20, 7), 2 * 2 * 16);
+        assert_eq!(BC1.level_size(1, 1), 8);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::buffer::Buffer;
+use crate::state;
+
+/// Captures vertex shader outputs (see `FeedbackVaryings`) into
+/// buffers. Remembers its buffer bindings and how many vertices were
+/// captured, so the result can be drawn without reading it back.
+pub struct TransformFeedback {
+    id: u32,
+}
+
+impl TransformFeedback {
+    /// Feedback objects need GL 4.0 or ARB_transform_feedback2.
+    pub fn new() -> Result<Self, &'static str> {
+        if crate::utils::gl::version() < (4, 0)
+            && !crate::utils::gl::has_extension("GL_ARB_transform_feedback2")
+        {
+            return Err("Transform feedback objects need GL 4.0");
+        }
+        let mut id = 0;
+        unsafe {
+            gl::GenTransformFeedbacks(1, &mut id);
+        }
+        Ok(Self { id })
+    }
+
+    pub fn bind(&self) {
+        unsafe { gl::BindTransformFeedback(gl::TRANSFORM_FEEDBACK, self.id) }
+    }
+
+    pub fn unbind() {
+        unsafe { gl::BindTransformFeedback(gl::TRANSFORM_FEEDBACK, 0) }
+    }
+
+    /// Captures into `buffer` at `index`, which is 0 for interleaved
+    /// varyings and the varying's position for separate ones. The
+    /// buffer can be of any type, f.i. a vertex buffer.
+    pub fn set_buffer(&self, index: u32, buffer: &Buffer) {
+        self.bind();
+        unsafe {
+            gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, index, buffer.id());
+        }
+        state::bound_indexed(gl::TRANSFORM_FEEDBACK_BUFFER, buffer.id());
+        Self::unbind();
+    }
+
+    /// Like `set_buffer`, for `size` bytes from `offset` (a multiple of 4).
+    pub fn set_buffer_range(&self, index: u32, buffer: &Buffer, offset: usize, size: usize) {
+        self.bind();
+        unsafe {
+            gl::BindBufferRange(
+                gl::TRANSFORM_FEEDBACK_BUFFER,
+                index,
+                buffer.id(),
+                offset as isize,
+                size as isize,
+            );
+        }
+        state::bound_indexed(gl::TRANSFORM_FEEDBACK_BUFFER, buffer.id());
+        Self::unbind();
+    }
+
+    /// Binds this object and starts capturing. Draw calls until `end`
+    /// must use `primitive` (strips and fans count as their base type)
+    /// and the program linked with the varyings must be bound.
+    pub fn begin(&self, primitive: Primitive) {
+        self.bind();
+        unsafe { gl::BeginTransformFeedback(primitive as u32) }
+    }
+
+    pub fn end(&self) {
+        unsafe { gl::EndTransformFeedback() }
+        Self::unbind();
+    }
+
+    /// Stops capturing without ending, f.i. to draw something else
+    /// in between. The object stays bound.
+    pub fn pause(&self) {
+        unsafe { gl::PauseTransformFeedback() }
+    }
+
+    pub fn resume(&self) {
+        unsafe { gl::ResumeTransformFeedback() }
+    }
+
+    /// Draws as many vertices as were captured by the last `begin`
+    /// and `end`, with the bound vertex array and program. The vertex
+    /// count never goes through the cpu, and `mode` does not have to
+    /// be the primitive that was captured.
+    pub fn draw(&self, mode: DrawMode) {
+        unsafe { gl::DrawTransformFeedback(mode as u32, self.id) }
+    }
+
+    /// Needs GL 4.2 or ARB_transform_feedback_instanced.
+    pub fn draw_instanced(&self, mode: DrawMode, instances: usize) -> Result<(), &'static str> {
+        if crate::utils::gl::version() < (4, 2)
+            && !crate::utils::gl::has_extension("GL_ARB_transform_feedback_instanced")
+        {
+            return Err("Instanced transform feedback draws need GL 4.2");
+        }
+        unsafe { gl::DrawTransformFeedbackInstanced(mode as u32, self.id, instances as i32) }
+        Ok(())
+    }
+}
+
+impl Drop for TransformFeedback {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTransformFeedbacks(1, &self.id) }
+    }
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Primitive {
+    Points = gl::POINTS,
+    Lines = gl::LINES,
+    Triangles = gl::TRIANGLES,
+}
+
+/// How `TransformFeedback::draw` assembles the captured vertices.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DrawMode {
+    Points = gl::POINTS,
+    Lines = gl::LINES,
+    LineStrip = gl::LINE_STRIP,
+    LineLoop = gl::LINE_LOOP,
+    Triangles = gl::TRIANGLES,
+    TriangleStrip = gl::TRIANGLE_STRIP,
+    TriangleFan = gl::TRIANGLE_FAN,
+    LinesAdjacency = gl::LINES_ADJACENCY,
+    LineStripAdjacency = gl::LINE_STRIP_ADJACENCY,
+    TrianglesAdjacency = gl::TRIANGLES_ADJACENCY,
+    TriangleStripAdjacency = gl::TRIANGLE_STRIP_ADJACENCY,
+}
+
+impl From<Primitive> for DrawMode {
+    fn from(primitive: Primitive) -> Self {
+        match primitive {
+            Primitive::Points => Self::Points,
+            Primitive::Lines => Self::Lines,
+            Primitive::Triangles => Self::Triangles,
+        }
+    }
+}
+
+/// Skips rasterization entirely, for passes that only capture.
+pub fn set_rasterizer_discard(discard: bool) {
+    unsafe {
+        if discard {
+            gl::Enable(gl::RASTERIZER_DISCARD);
+        } else {
+            gl::Disable(gl::RASTERIZER_DISCARD);
+        }
+    }
+}
+
+// Discards rasterization until dropped, also when a step panics.
+struct DiscardGuard;
+
+impl DiscardGuard {
+    fn new() -> Self {
+        set_rasterizer_discard(true);
+        Self
+    }
+}
+
+impl Drop for DiscardGuard {
+    fn drop(&mut self) {
+        set_rasterizer_discard(false);
+    }
+}
+
+/// Double buffered simulation state, f.i. particles: every step reads
+/// the source buffer and captures into the other one, then they swap.
+pub struct FeedbackPingPong {
+    buffers: [Buffer; 2],
+    feedbacks: [TransformFeedback; 2],
+    source: usize,
+    // whether the source was written by a step, it then has a count
+    captured: bool,
+}
+
+impl FeedbackPingPong {
+    /// The buffers need room for the captured vertices, `a` holds the
+    /// initial state. Both capture interleaved, at index 0. Needs
+    /// GL 4.0, see `TransformFeedback::new`.
+    pub fn new(a: Buffer, b: Buffer) -> Result<Self, &'static str> {
+        let feedbacks = [TransformFeedback::new()?, TransformFeedback::new()?];
+        feedbacks[0].set_buffer(0, &a);
+        feedbacks[1].set_buffer(0, &b);
+        Ok(Self {
+            buffers: [a, b],
+            feedbacks,
+            source: 0,
+            captured: false,
+        })
+    }
+
+    /// Holds the latest state.
+    pub fn source(&self) -> &Buffer {
+        &self.buffers[self.source]
+    }
+
+    /// Runs one step without rasterizing. `draw` gets the source buffer
+    /// and, once a step ran, the feedback that filled it, to draw with
+    /// `TransformFeedback::draw`. It sets up the vertex input from the
+    /// source and issues the draw, with the feedback program bound.
+    pub fn step<F>(&mut self, primitive: Primitive, draw: F)
+    where
+        F: FnOnce(&Buffer, Option<&TransformFeedback>),
+    {
+        let target = 1 - self.source;
+        let last = if self.captured {
+            Some(&self.feedbacks[self.source])
+        } else {
+            None
+        };
+        let discard = DiscardGuard::new();
+        self.feedbacks[target].begin(primitive);
+        draw(&self.buffers[self.source], last);
+        self.feedbacks[target].end();
+        drop(discard);
+        self.source = target;
+        self.captured = true;
+    }
+
+    /// Draws the latest state, f.i. to render the particles. Only
+    /// after a step, before that the vertex count is unknown.
+    pub fn draw(&self, mode: DrawMode) {
+        assert!(
+            self.captured,
+            "[ERROR] Nothing was captured yet, run a step first"
+        );
+        self.feedbacks[self.source].draw(mode);
+    }
+}
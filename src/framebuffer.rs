@@ -1,7 +1,17 @@
-use super::texture::Texture;
+use super::renderbuffer::Renderbuffer;
+use super::state;
+use super::texture::{Filtering, Format, Options, Texture, Wrapping};
+use image::RgbaImage;
+use std::ffi::c_void;
+use std::path::Path;
 
+/// A framebuffer owns its attachments, so textures rendered
+/// into stay alive and can be sampled through `texture`.
 pub struct FrameBuffer {
     id: u32,
+    attachments: Vec<(AttachmentType, Attachment)>,
+    clear_values: Vec<(AttachmentType, ClearValue)>,
+    size: Option<(u32, u32)>,
 }
 
 impl FrameBuffer {
@@ -10,37 +20,241 @@ impl FrameBuffer {
         unsafe {
             gl::GenFramebuffers(1, &mut id);
         }
-        Self { id }
+        Self {
+            id,
+            attachments: Vec::new(),
+            clear_values: Vec::new(),
+            size: None,
+        }
+    }
+    pub fn builder(width: u32, height: u32) -> FrameBufferBuilder {
+        FrameBufferBuilder::new(width, height)
     }
+    /// Checks this framebuffer, not whichever one is bound.
     pub fn ok(&self) -> Result<(), &'static str> {
-        if !unsafe { gl::CheckFramebufferStatus(gl::FRAMEBUFFER) == gl::FRAMEBUFFER_COMPLETE } {
+        let _bound = state::scoped_framebuffer(self.id);
+        let status = unsafe { gl::CheckFramebufferStatus(gl::FRAMEBUFFER) };
+        if status != gl::FRAMEBUFFER_COMPLETE {
             return Err("Framebuffer is not completed, cannot be used yet");
         }
         Ok(())
     }
+    /// The size of the attachments, known when made by the builder.
+    pub fn size(&self) -> Option<(u32, u32)> {
+        self.size
+    }
+    pub fn set_size(&mut self, size: (u32, u32)) {
+        self.size = Some(size);
+    }
+    /// Copies the `src` rect of this framebuffer's read buffer into the
+    /// `dst` rect of the draw buffers of `target`, rects are (x, y, w, h).
+    /// Depth and stencil can only be copied with `Filtering::Nearest`.
+    pub fn blit_to(
+        &self,
+        target: &impl BlitTarget,
+        src: Rect,
+        dst: Rect,
+        mask: BlitMask,
+        filter: Filtering,
+    ) {
+        blit(self.id, target.fbo_id(), src, dst, mask, filter);
+    }
+    /// Resolves a multisampled framebuffer into a regular one (or the
+    /// window), both must be the same size. Copies color attachment 0
+    /// and, when `with_depth`, depth and stencil.
+    pub fn resolve_to(
+        &self,
+        target: &impl BlitTarget,
+        with_depth: bool,
+    ) -> Result<(), &'static str> {
+        let size = self.size.ok_or("The framebuffer size is not known")?;
+        if target.size() != Some(size) {
+            return Err("Can only resolve into a target of the same size");
+        }
+        let mask = if with_depth {
+            BlitMask::ALL
+        } else {
+            BlitMask::COLOR
+        };
+        let rect = (0, 0, size.0, size.1);
+        self.blit_to(target, rect, rect, mask, Filtering::Nearest);
+        Ok(())
+    }
     pub fn bind(&self) {
-        unsafe { gl::BindFramebuffer(gl::FRAMEBUFFER, self.id) }
+        state::bind_framebuffer(self.id);
     }
     pub fn unbind() {
-        unsafe { gl::BindFramebuffer(gl::FRAMEBUFFER, 0) }
+        state::bind_framebuffer(0);
+    }
+    /// Binds until the guard drops, then the previous framebuffer.
+    pub fn bind_scoped(&self) -> state::Scoped {
+        state::scoped_framebuffer(self.id)
+    }
+    /// Reads a rect (x, y, w, h) of an attachment, rows ordered top to
+    /// bottom like images are. Every pixel has `format.n_channels()` T's.
+    pub fn read_pixels<T: PixelType>(
+        &self,
+        attachment: AttachmentType,
+        rect: Rect,
+        format: ReadFormat,
+    ) -> Vec<T> {
+        read_pixels(self.id, read_buffer_for(self.id, attachment), rect, format)
     }
-    pub fn attach_texture(&self, tex: Texture, type_: AttachmentType) {
+    /// Reads a whole color attachment as an rgba8 image.
+    pub fn read_image(&self, n: usize) -> Result<RgbaImage, &'static str> {
+        let (w, h) = self.size.ok_or("The framebuffer size is not known")?;
+        let pixels = self.read_pixels(AttachmentType::Color(n), (0, 0, w, h), ReadFormat::Rgba);
+        Ok(RgbaImage::from_raw(w, h, pixels).expect("[ERROR] Readback has the wrong size"))
+    }
+    /// Saves color attachment 0 as png.
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let img = self.read_image(0)?;
+        img.save_with_format(&path, image::ImageFormat::Png)
+            .map_err(|e| format!("Could not save png to '{:?}': {}", path.as_ref(), e))
+    }
+    /// The framebuffer must be bound. Replaces (and drops)
+    /// whatever was attached to the same point before.
+    pub fn attach_texture(
+        &mut self,
+        tex: Texture,
+        type_: AttachmentType,
+    ) -> Result<(), &'static str> {
+        type_.validate()?;
         unsafe {
             gl::FramebufferTexture2D(gl::FRAMEBUFFER, type_.gl(), gl::TEXTURE_2D, tex.id(), 0);
         }
+        self.store(type_, Attachment::Texture(tex));
+        Ok(())
+    }
+    /// The framebuffer must be bound.
+    pub fn attach_renderbuffer(
+        &mut self,
+        rbo: Renderbuffer,
+        type_: AttachmentType,
+    ) -> Result<(), &'static str> {
+        type_.validate()?;
+        unsafe {
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, type_.gl(), gl::RENDERBUFFER, rbo.id());
+        }
+        self.store(type_, Attachment::Renderbuffer(rbo));
+        Ok(())
+    }
+    /// The framebuffer must be bound. Gives back the attachment.
+    pub fn detach(&mut self, type_: AttachmentType) -> Option<Attachment> {
+        let i = self.attachments.iter().position(|(t, _)| *t == type_)?;
+        unsafe {
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, type_.gl(), gl::TEXTURE_2D, 0, 0);
+        }
+        let attachment = self.attachments.remove(i).1;
+        self.update_draw_buffers();
+        Some(attachment)
+    }
+    pub fn attachment(&self, type_: AttachmentType) -> Option<&Attachment> {
+        self.attachments
+            .iter()
+            .find(|(t, _)| *t == type_)
+            .map(|(_, a)| a)
+    }
+    pub fn attachments(&self) -> impl Iterator<Item = &(AttachmentType, Attachment)> {
+        self.attachments.iter()
+    }
+    /// The attached texture to sample from, None for renderbuffers.
+    pub fn texture(&self, type_: AttachmentType) -> Option<&Texture> {
+        match self.attachment(type_)? {
+            Attachment::Texture(tex) => Some(tex),
+            Attachment::Renderbuffer(_) => None,
+        }
+    }
+    pub fn color(&self, n: usize) -> Option<&Texture> {
+        self.texture(AttachmentType::Color(n))
+    }
+    /// The value `clear` writes to this attachment. Colors default
+    /// to transparent black, depth to 1.0 and stencil to 0.
+    pub fn set_clear_value(&mut self, type_: AttachmentType, value: ClearValue) {
+        self.clear_values.retain(|(t, _)| *t != type_);
+        self.clear_values.push((type_, value));
+    }
+    /// The framebuffer must be bound. Clears every attachment
+    /// to its own clear value.
+    pub fn clear(&self) {
+        for (type_, _) in self.attachments.iter() {
+            let value = self
+                .clear_values
+                .iter()
+                .find(|(t, _)| t == type_)
+                .map(|(_, v)| *v)
+                .unwrap_or_else(|| ClearValue::default_for(*type_));
+            Self::clear_attachment(*type_, value);
+        }
+    }
+    /// The framebuffer must be bound.
+    pub fn clear_attachment(type_: AttachmentType, value: ClearValue) {
+        // color attachment n is always draw buffer n, see update_draw_buffers
+        let draw_buffer = match type_ {
+            AttachmentType::Color(n) => n as i32,
+            _ => 0,
+        };
+        unsafe {
+            match value {
+                ClearValue::Color(c) => gl::ClearBufferfv(gl::COLOR, draw_buffer, c.as_ptr()),
+                ClearValue::Int(c) => gl::ClearBufferiv(gl::COLOR, draw_buffer, c.as_ptr()),
+                ClearValue::UInt(c) => gl::ClearBufferuiv(gl::COLOR, draw_buffer, c.as_ptr()),
+                ClearValue::Depth(d) => gl::ClearBufferfv(gl::DEPTH, 0, &d),
+                ClearValue::Stencil(s) => gl::ClearBufferiv(gl::STENCIL, 0, &s),
+                ClearValue::DepthStencil(d, s) => gl::ClearBufferfi(gl::DEPTH_STENCIL, 0, d, s),
+            }
+        }
+    }
+    fn store(&mut self, type_: AttachmentType, attachment: Attachment) {
+        self.attachments.retain(|(t, _)| *t != type_);
+        self.attachments.push((type_, attachment));
+        self.update_draw_buffers();
+    }
+    // Draws into every color attachment, with NONE for unused
+    // indices so that attachment n stays fragment output n.
+    fn update_draw_buffers(&self) {
+        let colors = self.attachments.iter().filter_map(|(t, _)| match t {
+            AttachmentType::Color(n) => Some(*n),
+            _ => None,
+        });
+        let n_buffers = colors.clone().max().map_or(0, |n| n + 1);
+        let mut buffers = vec![gl::NONE; n_buffers];
+        for n in colors.clone() {
+            buffers[n] = AttachmentType::Color(n).gl();
+        }
+        unsafe {
+            if buffers.is_empty() {
+                gl::DrawBuffer(gl::NONE);
+            } else {
+                gl::DrawBuffers(buffers.len() as i32, buffers.as_ptr());
+            }
+            gl::ReadBuffer(
+                colors
+                    .min()
+                    .map_or(gl::NONE, |n| AttachmentType::Color(n).gl()),
+            );
+        }
     }
 }
 
 impl Drop for FrameBuffer {
     fn drop(&mut self) {
         unsafe { gl::DeleteFramebuffers(1, &self.id as *const u32) }
+        state::deleted_framebuffer(self.id);
     }
 }
 
+pub enum Attachment {
+    Texture(Texture),
+    Renderbuffer(Renderbuffer),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum AttachmentType {
     Color(usize),
     Stencil,
     Depth,
+    DepthStencil,
 }
 
 impl AttachmentType {
@@ -49,6 +263,430 @@ impl AttachmentType {
             Self::Color(n) => gl::COLOR_ATTACHMENT0 + *n as u32,
             Self::Stencil => gl::STENCIL_ATTACHMENT,
             Self::Depth => gl::DEPTH_ATTACHMENT,
+            Self::DepthStencil => gl::DEPTH_STENCIL_ATTACHMENT,
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        match self {
+            Self::Color(n) if *n as u32 >= max_color_attachments() => {
+                Err("The color attachment exceeds GL_MAX_COLOR_ATTACHMENTS or GL_MAX_DRAW_BUFFERS")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Where a depth (and stencil) format should be attached.
+    pub fn depth_for(format: Format) -> Self {
+        if format.has_stencil() {
+            Self::DepthStencil
+        } else {
+            Self::Depth
+        }
+    }
+}
+
+/// (x, y, width, height) in pixels.
+pub type Rect = (u32, u32, u32, u32);
+
+/// Something to render or blit into: a `FrameBuffer`
+/// or the window's `DefaultFrameBuffer`.
+pub trait BlitTarget {
+    fn fbo_id(&self) -> u32;
+    fn size(&self) -> Option<(u32, u32)>;
+}
+
+impl BlitTarget for FrameBuffer {
+    fn fbo_id(&self) -> u32 {
+        self.id
+    }
+    fn size(&self) -> Option<(u32, u32)> {
+        self.size
+    }
+}
+
+/// Handle to the framebuffer of the window, which has id 0.
+/// Get one from `GlfwWindow::default_framebuffer` so the size is right.
+#[derive(Copy, Clone, Debug)]
+pub struct DefaultFrameBuffer {
+    pub size: (u32, u32),
+}
+
+impl DefaultFrameBuffer {
+    pub fn new(size: (u32, u32)) -> Self {
+        Self { size }
+    }
+    pub fn bind(&self) {
+        FrameBuffer::unbind();
+    }
+    /// Copies a rect of the window into `target`.
+    pub fn blit_to(
+        &self,
+        target: &impl BlitTarget,
+        src: Rect,
+        dst: Rect,
+        mask: BlitMask,
+        filter: Filtering,
+    ) {
+        blit(0, target.fbo_id(), src, dst, mask, filter);
+    }
+}
+
+impl DefaultFrameBuffer {
+    /// Reads a rect of the back buffer, so call this before swapping.
+    /// Rows are ordered top to bottom like images are.
+    pub fn read_pixels<T: PixelType>(&self, rect: Rect, format: ReadFormat) -> Vec<T> {
+        let read_buffer = match format {
+            ReadFormat::Depth | ReadFormat::Stencil | ReadFormat::DepthStencil => gl::NONE,
+            _ => gl::BACK,
+        };
+        read_pixels(0, read_buffer, rect, format)
+    }
+    pub fn read_image(&self) -> RgbaImage {
+        let (w, h) = self.size;
+        let pixels = self.read_pixels((0, 0, w, h), ReadFormat::Rgba);
+        RgbaImage::from_raw(w, h, pixels).expect("[ERROR] Readback has the wrong size")
+    }
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        self.read_image()
+            .save_with_format(&path, image::ImageFormat::Png)
+            .map_err(|e| format!("Could not save png to '{:?}': {}", path.as_ref(), e))
+    }
+}
+
+impl BlitTarget for DefaultFrameBuffer {
+    fn fbo_id(&self) -> u32 {
+        0
+    }
+    fn size(&self) -> Option<(u32, u32)> {
+        Some(self.size)
+    }
+}
+
+/// Which buffers `blit_to` copies, combine with `|`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlitMask(u32);
+
+impl BlitMask {
+    pub const COLOR: Self = Self(gl::COLOR_BUFFER_BIT);
+    pub const DEPTH: Self = Self(gl::DEPTH_BUFFER_BIT);
+    pub const STENCIL: Self = Self(gl::STENCIL_BUFFER_BIT);
+    pub const ALL: Self =
+        Self(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+}
+
+impl std::ops::BitOr for BlitMask {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// The pixel layout asked from `read_pixels`.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReadFormat {
+    Red = gl::RED,
+    Rg = gl::RG,
+    Rgb = gl::RGB,
+    Rgba = gl::RGBA,
+    RedInteger = gl::RED_INTEGER,
+    RgbaInteger = gl::RGBA_INTEGER,
+    Depth = gl::DEPTH_COMPONENT,
+    Stencil = gl::STENCIL_INDEX,
+    DepthStencil = gl::DEPTH_STENCIL,
+}
+
+impl ReadFormat {
+    pub fn n_channels(&self) -> usize {
+        match self {
+            Self::Red | Self::RedInteger | Self::Depth | Self::Stencil => 1,
+            Self::Rg => 2,
+            Self::Rgb => 3,
+            Self::Rgba | Self::RgbaInteger => 4,
+            // packed into a single DepthStencil24_8
+            Self::DepthStencil => 1,
+        }
+    }
+}
+
+/// Component types `read_pixels` can read into.
+pub trait PixelType: Copy + Default {
+    const GL_TYPE: u32;
+}
+
+impl PixelType for u8 {
+    const GL_TYPE: u32 = gl::UNSIGNED_BYTE;
+}
+
+impl PixelType for u16 {
+    const GL_TYPE: u32 = gl::UNSIGNED_SHORT;
+}
+
+impl PixelType for i32 {
+    const GL_TYPE: u32 = gl::INT;
+}
+
+impl PixelType for f32 {
+    const GL_TYPE: u32 = gl::FLOAT;
+}
+
+impl PixelType for u32 {
+    const GL_TYPE: u32 = gl::UNSIGNED_INT;
+}
+
+/// Depth and stencil packed into one value, the type to read
+/// `ReadFormat::DepthStencil` into.
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DepthStencil24_8(pub u32);
+
+impl DepthStencil24_8 {
+    /// Normalized to 0..1.
+    pub fn depth(&self) -> f32 {
+        (self.0 >> 8) as f32 / 0xFF_FFFF as f32
+    }
+
+    pub fn stencil(&self) -> u8 {
+        self.0 as u8
+    }
+}
+
+impl PixelType for DepthStencil24_8 {
+    const GL_TYPE: u32 = gl::UNSIGNED_INT_24_8;
+}
+
+// Leaves the read binding and read buffer as they were.
+fn read_pixels<T: PixelType>(
+    fbo_id: u32,
+    read_buffer: u32,
+    rect: Rect,
+    format: ReadFormat,
+) -> Vec<T> {
+    let row = rect.2 as usize * format.n_channels();
+    let mut pixels = vec![T::default(); row * rect.3 as usize];
+    unsafe {
+        read_pixels_into(
+            fbo_id,
+            read_buffer,
+            rect,
+            format,
+            T::GL_TYPE,
+            pixels.as_mut_ptr() as *mut c_void,
+        );
+    }
+    flip_rows(&mut pixels, row);
+    pixels
+}
+
+// Leaves the read binding and read buffer as they were. With a pixel
+// pack buffer bound `dst` is an offset into it, which makes this async.
+pub(crate) unsafe fn read_pixels_into(
+    fbo_id: u32,
+    read_buffer: u32,
+    (x, y, w, h): Rect,
+    format: ReadFormat,
+    gl_type: u32,
+    dst: *mut c_void,
+) {
+    let previous_fbo = bound(gl::READ_FRAMEBUFFER_BINDING);
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, fbo_id);
+    let previous_buffer = bound(gl::READ_BUFFER);
+    if read_buffer != gl::NONE {
+        gl::ReadBuffer(read_buffer);
+    }
+    let previous_alignment = bound(gl::PACK_ALIGNMENT);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(
+        x as i32,
+        y as i32,
+        w as i32,
+        h as i32,
+        format as u32,
+        gl_type,
+        dst,
+    );
+    gl::PixelStorei(gl::PACK_ALIGNMENT, previous_alignment as i32);
+    if read_buffer != gl::NONE {
+        gl::ReadBuffer(previous_buffer);
+    }
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous_fbo);
+}
+
+// The buffer ReadPixels should read from for an attachment,
+// NONE for depth and stencil which do not use the read buffer.
+pub(crate) fn read_buffer_for(fbo_id: u32, attachment: AttachmentType) -> u32 {
+    match attachment {
+        AttachmentType::Color(_) if fbo_id == 0 => gl::BACK,
+        AttachmentType::Color(_) => attachment.gl(),
+        _ => gl::NONE,
+    }
+}
+
+// GL reads bottom to top, images go top to bottom.
+pub(crate) fn flip_rows<T>(pixels: &mut [T], row: usize) {
+    if row == 0 {
+        return;
+    }
+    let n_rows = pixels.len() / row;
+    for i in 0..n_rows / 2 {
+        let (top, bottom) = pixels.split_at_mut((n_rows - 1 - i) * row);
+        top[i * row..(i + 1) * row].swap_with_slice(&mut bottom[..row]);
+    }
+}
+
+// Leaves the read and draw bindings as they were.
+fn blit(src_id: u32, dst_id: u32, src: Rect, dst: Rect, mask: BlitMask, filter: Filtering) {
+    let previous_read = bound(gl::READ_FRAMEBUFFER_BINDING);
+    let previous_draw = bound(gl::DRAW_FRAMEBUFFER_BINDING);
+    unsafe {
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, src_id);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, dst_id);
+        gl::BlitFramebuffer(
+            src.0 as i32,
+            src.1 as i32,
+            (src.0 + src.2) as i32,
+            (src.1 + src.3) as i32,
+            dst.0 as i32,
+            dst.1 as i32,
+            (dst.0 + dst.2) as i32,
+            (dst.1 + dst.3) as i32,
+            mask.0,
+            filter.gl(),
+        );
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous_read);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, previous_draw);
+    }
+}
+
+fn bound(binding: u32) -> u32 {
+    let mut id = 0;
+    unsafe {
+        gl::GetIntegerv(binding, &mut id);
+    }
+    id as u32
+}
+
+/// The number of usable color attachments, the minimum of
+/// GL_MAX_COLOR_ATTACHMENTS and GL_MAX_DRAW_BUFFERS.
+pub fn max_color_attachments() -> u32 {
+    let mut attachments = 0;
+    let mut draw_buffers = 0;
+    unsafe {
+        gl::GetIntegerv(gl::MAX_COLOR_ATTACHMENTS, &mut attachments);
+        gl::GetIntegerv(gl::MAX_DRAW_BUFFERS, &mut draw_buffers);
+    }
+    attachments.min(draw_buffers).max(1) as u32
+}
+
+/// Per attachment clear value, the variant must match the attachment:
+/// `Int`/`UInt` for integer color formats, `Color` for the rest.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClearValue {
+    Color([f32; 4]),
+    Int([i32; 4]),
+    UInt([u32; 4]),
+    Depth(f32),
+    Stencil(i32),
+    DepthStencil(f32, i32),
+}
+
+impl ClearValue {
+    pub fn default_for(type_: AttachmentType) -> Self {
+        match type_ {
+            AttachmentType::Color(_) => Self::Color([0.0; 4]),
+            AttachmentType::Depth => Self::Depth(1.0),
+            AttachmentType::Stencil => Self::Stencil(0),
+            AttachmentType::DepthStencil => Self::DepthStencil(1.0, 0),
+        }
+    }
+}
+
+/// ```ignore
+/// let fbo = FrameBuffer::builder(w, h)
+///     .color(Format::Rgba16F)
+///     .depth(Format::Depth24Stencil8)
+///     .build()?;
+/// ```
+pub struct FrameBufferBuilder {
+    size: (u32, u32),
+    options: Options,
+    colors: Vec<(Format, bool)>,
+    depth: Option<(Format, bool)>,
+    samples: u32,
+}
+
+impl FrameBufferBuilder {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            size: (width, height),
+            options: Options::default()
+                .mipmap(false)
+                .wrapping(Wrapping::ClampEdge)
+                .filtering(Filtering::Linear),
+            colors: Vec::new(),
+            depth: None,
+            samples: 0,
+        }
+    }
+
+    /// Sampling options of the attached textures.
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Adds a color texture at the next color attachment.
+    pub fn color(mut self, format: Format) -> Self {
+        self.colors.push((format, true));
+        self
+    }
+
+    /// Adds a color renderbuffer at the next color attachment.
+    pub fn color_renderbuffer(mut self, format: Format) -> Self {
+        self.colors.push((format, false));
+        self
+    }
+
+    /// A depth (and stencil) renderbuffer.
+    pub fn depth(mut self, format: Format) -> Self {
+        self.depth = Some((format, false));
+        self
+    }
+
+    /// A depth (and stencil) texture, f.i. for shadow maps.
+    pub fn depth_texture(mut self, format: Format) -> Self {
+        self.depth = Some((format, true));
+        self
+    }
+
+    /// Makes every attachment a multisampled renderbuffer, textures
+    /// cannot be multisampled here. Resolve with `resolve_to`.
+    pub fn samples(mut self, samples: u32) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn build(self) -> Result<FrameBuffer, &'static str> {
+        let mut fbo = FrameBuffer::new();
+        fbo.set_size(self.size);
+        let _bound = state::scoped_framebuffer(fbo.id);
+        let targets = self
+            .colors
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (AttachmentType::Color(i), *c))
+            .chain(self.depth.map(|d| (AttachmentType::depth_for(d.0), d)));
+        for (type_, (format, is_texture)) in targets {
+            let res = if is_texture && self.samples == 0 {
+                let tex = Texture::empty_with_format(self.options, self.size, format);
+                fbo.attach_texture(tex, type_)
+            } else {
+                let rbo = Renderbuffer::multisampled(format, self.size, self.samples);
+                fbo.attach_renderbuffer(rbo, type_)
+            };
+            res?;
         }
+        fbo.ok().map(|_| fbo)
     }
 }
@@ -0,0 +1,114 @@
+// A GL context without a window or display server, through EGL.
+// Only built with the `headless` feature, which links libEGL.
+
+use khronos_egl as egl;
+
+// EGL_MESA_platform_surfaceless
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+/// A GL 3.2 core context that renders only into framebuffers, f.i.
+/// for tests on CI machines. Made current on creation, so create it
+/// on the thread that renders. Uses Mesa's surfaceless platform when
+/// available, otherwise the default EGL display.
+pub struct HeadlessContext {
+    egl: egl::Instance<egl::Static>,
+    display: egl::Display,
+    context: egl::Context,
+}
+
+impl HeadlessContext {
+    pub fn new() -> Result<Self, String> {
+        let egl = egl::Instance::new(egl::Static);
+        let display = unsafe {
+            egl.get_platform_display(
+                PLATFORM_SURFACELESS_MESA,
+                egl::DEFAULT_DISPLAY,
+                &[egl::ATTRIB_NONE],
+            )
+            .ok()
+            .or_else(|| egl.get_display(egl::DEFAULT_DISPLAY))
+        }
+        .ok_or("No EGL display")?;
+        egl.initialize(display)
+            .map_err(|e| format!("Could not initialize EGL: {}", e))?;
+
+        let context = match Self::create_context(&egl, display) {
+            Ok(context) => context,
+            Err(e) => {
+                let _ = egl.terminate(display);
+                return Err(e);
+            }
+        };
+        let this = Self {
+            egl,
+            display,
+            context,
+        };
+        this.egl
+            .make_current(display, None, None, Some(context))
+            .map_err(|e| format!("Could not make the EGL context current: {}", e))?;
+        gl::load_with(|s| {
+            this.egl
+                .get_proc_address(s)
+                .map_or(std::ptr::null(), |f| f as *const _)
+        });
+        // a fresh context, nothing of it is cached yet
+        crate::state::invalidate();
+        Ok(this)
+    }
+
+    fn create_context(
+        egl: &egl::Instance<egl::Static>,
+        display: egl::Display,
+    ) -> Result<egl::Context, String> {
+        let extensions = egl
+            .query_string(Some(display), egl::EXTENSIONS)
+            .map_err(|e| format!("Could not query EGL extensions: {}", e))?
+            .to_string_lossy();
+        if !extensions
+            .split(' ')
+            .any(|e| e == "EGL_KHR_surfaceless_context")
+        {
+            return Err("EGL does not support contexts without a surface".into());
+        }
+        egl.bind_api(egl::OPENGL_API)
+            .map_err(|e| format!("EGL does not support desktop GL: {}", e))?;
+        let config = egl
+            .choose_first_config(
+                display,
+                // the default is window surfaces, which there are none of
+                &[
+                    egl::RENDERABLE_TYPE,
+                    egl::OPENGL_BIT,
+                    egl::SURFACE_TYPE,
+                    egl::DONT_CARE,
+                    egl::NONE,
+                ],
+            )
+            .map_err(|e| format!("Could not choose an EGL config: {}", e))?
+            .ok_or("No EGL config supports desktop GL")?;
+        egl.create_context(
+            display,
+            config,
+            None,
+            &[
+                egl::CONTEXT_MAJOR_VERSION,
+                3,
+                egl::CONTEXT_MINOR_VERSION,
+                2,
+                egl::CONTEXT_OPENGL_PROFILE_MASK,
+                egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+                egl::NONE,
+            ],
+        )
+        .map_err(|e| format!("Could not create a GL 3.2 context: {}", e))
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_context(self.display, self.context);
+        let _ = self.egl.terminate(self.display);
+    }
+}
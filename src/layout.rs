@@ -0,0 +1,449 @@
+use crate::buffer::{Buffer, BufferType, DrawType};
+use crate::glm;
+use std::marker::PhantomData;
+
+/// The memory layouts of interface blocks. Std140 is what uniform
+/// blocks use, std430 (storage blocks only) packs arrays and structs
+/// of scalars and vec2's tighter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Std140,
+    Std430,
+}
+
+/// Anything that can be a member of a uniform or storage block.
+/// Implemented for scalars, glm vectors and matrices, arrays of
+/// those, and structs declared with `block_struct!`.
+pub trait BlockField {
+    /// The base alignment in bytes.
+    fn align(layout: Layout) -> usize;
+    /// The size in bytes, arrays and structs include their padding.
+    fn size(layout: Layout) -> usize;
+    /// Appends the padded bytes, the writer is already aligned.
+    fn write(&self, writer: &mut BlockWriter);
+}
+
+/// Serializes values into correctly padded block bytes.
+pub struct BlockWriter {
+    layout: Layout,
+    bytes: Vec<u8>,
+}
+
+impl BlockWriter {
+    pub fn new(layout: Layout) -> Self {
+        Self {
+            layout,
+            bytes: Vec::new(),
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Pads to the alignment of T, then writes the value.
+    pub fn field<T: BlockField>(&mut self, value: &T) {
+        self.pad_to(T::align(self.layout));
+        value.write(self);
+    }
+
+    pub fn pad_to(&mut self, align: usize) {
+        let len = round_up(self.bytes.len(), align);
+        self.bytes.resize(len, 0);
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    fn raw(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+/// All bytes of a value as the root of a block.
+pub fn to_bytes<T: BlockField>(value: &T, layout: Layout) -> Vec<u8> {
+    let mut writer = BlockWriter::new(layout);
+    writer.field(value);
+    writer.into_bytes()
+}
+
+pub fn round_up(n: usize, align: usize) -> usize {
+    n.div_ceil(align.max(1)) * align.max(1)
+}
+
+macro_rules! impl_scalar {
+    ($($ty:ty),*) => {$(
+        impl BlockField for $ty {
+            fn align(_: Layout) -> usize {
+                4
+            }
+            fn size(_: Layout) -> usize {
+                4
+            }
+            fn write(&self, writer: &mut BlockWriter) {
+                writer.raw(&self.to_ne_bytes());
+            }
+        }
+    )*};
+}
+
+impl_scalar!(f32, i32, u32);
+
+// glsl bools are 4 bytes
+impl BlockField for bool {
+    fn align(_: Layout) -> usize {
+        4
+    }
+    fn size(_: Layout) -> usize {
+        4
+    }
+    fn write(&self, writer: &mut BlockWriter) {
+        (*self as u32).write(writer);
+    }
+}
+
+macro_rules! impl_vector {
+    ($($ty:ty: $n:expr),*) => {$(
+        impl BlockField for $ty {
+            fn align(_: Layout) -> usize {
+                // a vec3 aligns like a vec4
+                if $n == 2 { 8 } else { 16 }
+            }
+            fn size(_: Layout) -> usize {
+                4 * $n
+            }
+            fn write(&self, writer: &mut BlockWriter) {
+                for c in self.iter() {
+                    c.write(writer);
+                }
+            }
+        }
+    )*};
+}
+
+impl_vector!(
+    glm::Vec2: 2, glm::Vec3: 3, glm::Vec4: 4,
+    glm::IVec2: 2, glm::IVec3: 3, glm::IVec4: 4,
+    glm::UVec2: 2, glm::UVec3: 3, glm::UVec4: 4
+);
+
+// Matrices are stored as an array of their column vectors.
+macro_rules! impl_matrix {
+    ($($ty:ty: $column:ty, $n_columns:expr),*) => {$(
+        impl BlockField for $ty {
+            fn align(layout: Layout) -> usize {
+                array_align::<$column>(layout)
+            }
+            fn size(layout: Layout) -> usize {
+                array_stride::<$column>(layout) * $n_columns
+            }
+            fn write(&self, writer: &mut BlockWriter) {
+                let stride = array_stride::<$column>(writer.layout());
+                for column in self.column_iter() {
+                    let start = writer.bytes.len();
+                    let column: $column = column.into_owned();
+                    column.write(writer);
+                    writer.bytes.resize(start + stride, 0);
+                }
+            }
+        }
+    )*};
+}
+
+impl_matrix!(
+    glm::Mat2: glm::Vec2, 2,
+    glm::Mat3: glm::Vec3, 3,
+    glm::Mat4: glm::Vec4, 4
+);
+
+fn array_align<T: BlockField>(layout: Layout) -> usize {
+    match layout {
+        Layout::Std140 => round_up(T::align(layout), 16),
+        Layout::Std430 => T::align(layout),
+    }
+}
+
+fn array_stride<T: BlockField>(layout: Layout) -> usize {
+    round_up(T::size(layout), array_align::<T>(layout))
+}
+
+impl<T: BlockField, const N: usize> BlockField for [T; N] {
+    fn align(layout: Layout) -> usize {
+        array_align::<T>(layout)
+    }
+    fn size(layout: Layout) -> usize {
+        array_stride::<T>(layout) * N
+    }
+    fn write(&self, writer: &mut BlockWriter) {
+        let stride = array_stride::<T>(writer.layout());
+        for element in self.iter() {
+            let start = writer.bytes.len();
+            element.write(writer);
+            writer.bytes.resize(start + stride, 0);
+        }
+    }
+}
+
+/// Declares a struct and implements `BlockField` for it, so it
+/// can be uploaded with std140 or std430 padding. Members are laid
+/// out in declaration order, like the glsl block they mirror.
+///
+/// ```ignore
+/// block_struct! {
+///     pub struct Light {
+///         pub position: glm::Vec3,
+///         pub intensity: f32,
+///         pub color: glm::Vec4,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! block_struct {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$fmeta:meta])* $fvis:vis $field:ident : $fty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$fmeta])* $fvis $field: $fty),*
+        }
+
+        impl $name {
+            /// The byte offset of every member, as glsl sees them.
+            #[allow(dead_code)]
+            pub fn offsets(layout: $crate::layout::Layout) -> Vec<(&'static str, usize)> {
+                let mut offset = 0;
+                let mut offsets = Vec::new();
+                $(
+                    offset = $crate::layout::round_up(
+                        offset,
+                        <$fty as $crate::layout::BlockField>::align(layout),
+                    );
+                    offsets.push((stringify!($field), offset));
+                    offset += <$fty as $crate::layout::BlockField>::size(layout);
+                )*
+                let _ = offset;
+                offsets
+            }
+        }
+
+        impl $crate::layout::BlockField for $name {
+            fn align(layout: $crate::layout::Layout) -> usize {
+                let align = 4 $(.max(<$fty as $crate::layout::BlockField>::align(layout)))*;
+                match layout {
+                    $crate::layout::Layout::Std140 => $crate::layout::round_up(align, 16),
+                    $crate::layout::Layout::Std430 => align,
+                }
+            }
+            fn size(layout: $crate::layout::Layout) -> usize {
+                let mut size = 0;
+                $(
+                    size = $crate::layout::round_up(
+                        size,
+                        <$fty as $crate::layout::BlockField>::align(layout),
+                    );
+                    size += <$fty as $crate::layout::BlockField>::size(layout);
+                )*
+                $crate::layout::round_up(size, Self::align(layout))
+            }
+            fn write(&self, writer: &mut $crate::layout::BlockWriter) {
+                let align = Self::align(writer.layout());
+                $(writer.field(&self.$field);)*
+                writer.pad_to(align);
+            }
+        }
+    };
+}
+
+/// A buffer holding a single block value of type T. Uniform buffers
+/// use std140, storage buffers std430.
+pub struct UniformBuffer<T> {
+    buffer: Buffer,
+    layout: Layout,
+    _type: PhantomData<T>,
+}
+
+impl<T: BlockField> UniformBuffer<T> {
+    /// For a `uniform` block, with std140 layout.
+    pub fn new() -> Self {
+        Self::with_layout(BufferType::Uniform, Layout::Std140)
+    }
+
+    /// For a `buffer` (shader storage) block, with std430 layout.
+    pub fn new_storage() -> Self {
+        Self::with_layout(BufferType::ShaderStorage, Layout::Std430)
+    }
+
+    pub fn with_layout(buffertype: BufferType, layout: Layout) -> Self {
+        let mut buffer = Buffer::new(buffertype, DrawType::Dynamic);
+        buffer.bind();
+        buffer.init(T::size(layout));
+        buffer.unbind();
+        Self {
+            buffer,
+            layout,
+            _type: PhantomData,
+        }
+    }
+
+    pub fn set(&self, value: &T) {
+        let bytes = to_bytes(value, self.layout);
+        self.buffer.bind();
+        self.buffer.subbuffer(&bytes, 0);
+        self.buffer.unbind();
+    }
+
+    /// Same as `Buffer::set_binding`, pair it with
+    /// `ShaderProgram::bind_uniform_block` (or `bind_storage_block`).
+    pub fn set_binding(&self, binding: usize) {
+        self.buffer.set_binding(binding);
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+}
+
+impl<T: BlockField> Default for UniformBuffer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Layout::*;
+
+    crate::block_struct! {
+        struct Vec3ThenFloat {
+            a: glm::Vec3,
+            b: f32,
+        }
+    }
+
+    crate::block_struct! {
+        struct Inner {
+            x: f32,
+        }
+    }
+
+    crate::block_struct! {
+        struct Outer {
+            a: f32,
+            inner: Inner,
+            b: f32,
+            m: glm::Mat3,
+            c: f32,
+        }
+    }
+
+    crate::block_struct! {
+        struct Arrays {
+            floats: [f32; 3],
+            vec2s: [glm::Vec2; 2],
+            vec3s: [glm::Vec3; 2],
+            after: f32,
+        }
+    }
+
+    fn f32_at(bytes: &[u8], offset: usize) -> f32 {
+        let mut b = [0; 4];
+        b.copy_from_slice(&bytes[offset..offset + 4]);
+        f32::from_ne_bytes(b)
+    }
+
+    #[test]
+    fn scalar_after_vec3() {
+        for &layout in [Std140, Std430].iter() {
+            assert_eq!(Vec3ThenFloat::offsets(layout), vec![("a", 0), ("b", 12)]);
+            assert_eq!(Vec3ThenFloat::size(layout), 16);
+            assert_eq!(Vec3ThenFloat::align(layout), 16);
+        }
+    }
+
+    #[test]
+    fn nested_structs() {
+        assert_eq!(Inner::align(Std140), 16);
+        assert_eq!(Inner::size(Std140), 16);
+        assert_eq!(Inner::align(Std430), 4);
+        assert_eq!(Inner::size(Std430), 4);
+        assert_eq!(
+            Outer::offsets(Std140),
+            vec![("a", 0), ("inner", 16), ("b", 32), ("m", 48), ("c", 96)]
+        );
+        assert_eq!(Outer::size(Std140), 112);
+        assert_eq!(
+            Outer::offsets(Std430),
+            vec![("a", 0), ("inner", 4), ("b", 8), ("m", 16), ("c", 64)]
+        );
+        assert_eq!(Outer::size(Std430), 80);
+    }
+
+    #[test]
+    fn matrices() {
+        assert_eq!(glm::Mat2::size(Std140), 32);
+        assert_eq!(glm::Mat2::size(Std430), 16);
+        for &layout in [Std140, Std430].iter() {
+            assert_eq!(glm::Mat3::align(layout), 16);
+            assert_eq!(glm::Mat3::size(layout), 48);
+            assert_eq!(glm::Mat4::size(layout), 64);
+        }
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(<[f32; 3]>::size(Std140), 48);
+        assert_eq!(<[f32; 3]>::size(Std430), 12);
+        assert_eq!(<[glm::Vec3; 2]>::size(Std140), 32);
+        assert_eq!(<[glm::Vec3; 2]>::size(Std430), 32);
+        assert_eq!(
+            Arrays::offsets(Std140),
+            vec![("floats", 0), ("vec2s", 48), ("vec3s", 80), ("after", 112)]
+        );
+        assert_eq!(
+            Arrays::offsets(Std430),
+            vec![("floats", 0), ("vec2s", 16), ("vec3s", 32), ("after", 64)]
+        );
+    }
+
+    #[test]
+    fn written_bytes_follow_offsets() {
+        let value = Outer {
+            a: 1.0,
+            inner: Inner { x: 2.0 },
+            b: 3.0,
+            m: glm::mat3(4.0, 5.0, 6.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0),
+            c: 7.0,
+        };
+        for &layout in [Std140, Std430].iter() {
+            let bytes = to_bytes(&value, layout);
+            assert_eq!(bytes.len(), Outer::size(layout));
+            let offsets = Outer::offsets(layout);
+            assert_eq!(f32_at(&bytes, offsets[0].1), 1.0);
+            assert_eq!(f32_at(&bytes, offsets[1].1), 2.0);
+            assert_eq!(f32_at(&bytes, offsets[2].1), 3.0);
+            assert_eq!(f32_at(&bytes, offsets[4].1), 7.0);
+            // the first row holds the first component of every column
+            let m = offsets[3].1;
+            assert_eq!(f32_at(&bytes, m), 4.0);
+            assert_eq!(f32_at(&bytes, m + 16), 5.0);
+            assert_eq!(f32_at(&bytes, m + 32), 6.0);
+        }
+
+        let floats = to_bytes(&[1.0f32, 2.0, 3.0], Std140);
+        assert_eq!(floats.len(), 48);
+        assert_eq!(f32_at(&floats, 16), 2.0);
+        assert_eq!(f32_at(&floats, 32), 3.0);
+        let floats = to_bytes(&[1.0f32, 2.0, 3.0], Std430);
+        assert_eq!(floats.len(), 12);
+        assert_eq!(f32_at(&floats, 4), 2.0);
+    }
+}
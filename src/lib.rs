@@ -1,11 +1,27 @@
 #[macro_use]
 pub mod utils;
+pub mod atlas;
+pub mod binder;
 pub mod buffer;
+pub mod compressed;
+pub mod feedback;
 pub mod framebuffer;
+#[cfg(feature = "headless")]
+pub mod headless;
+pub mod layout;
+pub mod pbo;
+pub mod profiler;
+pub mod programcache;
 pub mod query;
+pub mod renderbuffer;
+pub mod renderstate;
 pub mod sampler;
 pub mod settings;
 pub mod shader;
+pub mod state;
+pub mod stream;
+pub mod sync;
+pub mod testing;
 pub mod texture;
 pub mod vao;
 pub mod window;
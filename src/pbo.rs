@@ -0,0 +1,157 @@
+use crate::buffer::{Buffer, BufferType, DrawType};
+use crate::framebuffer::{self, AttachmentType, BlitTarget, PixelType, ReadFormat, Rect};
+use crate::sync::FencedQueue;
+use crate::texture::Texture;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+/// Uploads rgba8 texture data through a pixel unpack buffer, so
+/// `glTexSubImage2D` returns without waiting for the transfer.
+pub struct PixelUploader {
+    pbo: Buffer,
+}
+
+impl Default for PixelUploader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PixelUploader {
+    pub fn new() -> Self {
+        Self {
+            pbo: Buffer::new(BufferType::PixelUnpack, DrawType::Stream),
+        }
+    }
+
+    pub fn upload<T>(&mut self, texture: &Texture, data: &[T], pos: (u32, u32), size: (u32, u32)) {
+        let n_bytes = std::mem::size_of_val(data);
+        let needed = (size.0 as usize)
+            .checked_mul(size.1 as usize)
+            .and_then(|n| n.checked_mul(4));
+        assert!(
+            matches!(needed, Some(needed) if n_bytes >= needed),
+            "[ERROR] Not enough data for an rgba8 region of {:?}",
+            size
+        );
+        self.pbo.bind();
+        // orphan, so the gpu can still read the previous upload
+        self.pbo.init(n_bytes);
+        self.pbo.subbuffer(data, 0);
+        unsafe {
+            texture.update_region_raw(std::ptr::null(), pos, size);
+        }
+        self.pbo.unbind();
+    }
+}
+
+/// Non-blocking `glReadPixels`: reads go into pixel pack buffers
+/// and are handed back in order once their fence is signaled,
+/// usually a frame or two later.
+pub struct PixelReader<T> {
+    pending: FencedQueue<PendingRead>,
+    free: Vec<Buffer>,
+    max_in_flight: usize,
+    _type: PhantomData<T>,
+}
+
+struct PendingRead {
+    pbo: Buffer,
+    rect: Rect,
+    format: ReadFormat,
+}
+
+pub struct Readback<T> {
+    pub rect: Rect,
+    pub format: ReadFormat,
+    /// rows ordered top to bottom, like `FrameBuffer::read_pixels`
+    pub pixels: Vec<T>,
+}
+
+impl<T: PixelType> PixelReader<T> {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            pending: FencedQueue::new(),
+            free: Vec::new(),
+            max_in_flight: max_in_flight.max(1),
+            _type: PhantomData,
+        }
+    }
+
+    /// Starts reading a rect of `target`. Fails when `max_in_flight`
+    /// reads are still pending, take their results first.
+    pub fn read(
+        &mut self,
+        target: &impl BlitTarget,
+        attachment: AttachmentType,
+        rect: Rect,
+        format: ReadFormat,
+    ) -> Result<(), &'static str> {
+        if self.pending.len() >= self.max_in_flight {
+            return Err("Too many pixel reads in flight");
+        }
+        let mut pbo = self
+            .free
+            .pop()
+            .unwrap_or_else(|| Buffer::new(BufferType::PixelPack, DrawType::StreamRead));
+        let n_bytes = Self::n_elements(rect, format) * std::mem::size_of::<T>();
+        let fbo_id = target.fbo_id();
+        pbo.bind();
+        pbo.init(n_bytes);
+        unsafe {
+            framebuffer::read_pixels_into(
+                fbo_id,
+                framebuffer::read_buffer_for(fbo_id, attachment),
+                rect,
+                format,
+                T::GL_TYPE,
+                std::ptr::null_mut(),
+            );
+        }
+        pbo.unbind();
+        self.pending.push(PendingRead { pbo, rect, format });
+        Ok(())
+    }
+
+    /// The oldest read if it is done, without blocking.
+    pub fn try_take(&mut self) -> Option<Readback<T>> {
+        let read = self.pending.pop_signaled()?;
+        Some(self.finish(read))
+    }
+
+    /// The oldest read, blocks until it is done. None when nothing
+    /// is pending or waiting failed, f.i. the context was lost.
+    pub fn take(&mut self) -> Option<Readback<T>> {
+        let read = self.pending.pop_wait()?.ok()?;
+        Some(self.finish(read))
+    }
+
+    /// The oldest read, if it is done within `timeout`.
+    pub fn take_timeout(&mut self, timeout: Duration) -> Option<Readback<T>> {
+        let read = self.pending.pop_timeout(timeout)?;
+        Some(self.finish(read))
+    }
+
+    fn finish(&mut self, read: PendingRead) -> Readback<T> {
+        let mut pixels = vec![T::default(); Self::n_elements(read.rect, read.format)];
+        read.pbo.bind();
+        read.pbo.download(&mut pixels, 0);
+        read.pbo.unbind();
+        let row = read.rect.2 as usize * read.format.n_channels();
+        framebuffer::flip_rows(&mut pixels, row);
+        self.free.push(read.pbo);
+        Readback {
+            rect: read.rect,
+            format: read.format,
+            pixels,
+        }
+    }
+
+    pub fn n_pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn n_elements((_, _, w, h): Rect, format: ReadFormat) -> usize {
+        w as usize * h as usize * format.n_channels()
+    }
+}
@@ -0,0 +1,323 @@
+use crate::query::{Query, Target};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+
+/// Times nested scopes of gpu work with timestamp queries. Results
+/// are read `latency` frames later, so the cpu normally never waits
+/// on the gpu, and queries are reused across frames. When results
+/// are more than `2 * latency` frames late, `end_frame` waits.
+///
+/// ```ignore
+/// let mut profiler = GpuProfiler::new(3);
+/// loop {
+///     profiler.begin_frame();
+///     {
+///         let mut frame = profiler.scope("frame");
+///         {
+///             let _shadows = frame.scope("shadow pass");
+///             // draw ...
+///         }
+///     }
+///     profiler.end_frame();
+/// }
+/// ```
+pub struct GpuProfiler {
+    latency: usize,
+    window: usize,
+    free: Vec<Query>,
+    pending: VecDeque<Frame>,
+    current: Frame,
+    // indices into current.scopes of the open scopes
+    open: Vec<usize>,
+    stats: HashMap<String, Stats>,
+    // paths in the order they were first seen
+    order: Vec<String>,
+    trace: VecDeque<TraceEvent>,
+    max_trace_events: usize,
+    // first timestamp, trace times are relative to it
+    epoch: Option<i64>,
+    frame_index: u64,
+}
+
+#[derive(Default)]
+struct Frame {
+    index: u64,
+    scopes: Vec<Scope>,
+}
+
+struct Scope {
+    name: String,
+    path: String,
+    depth: usize,
+    start: Query,
+    end: Option<Query>,
+}
+
+struct Stats {
+    name: String,
+    depth: usize,
+    samples: VecDeque<f64>,
+}
+
+struct TraceEvent {
+    name: String,
+    frame: u64,
+    depth: usize,
+    start_ns: i64,
+    duration_ns: i64,
+}
+
+/// The timings of one scope, in milliseconds.
+#[derive(Clone, Debug)]
+pub struct ScopeTiming {
+    pub name: String,
+    /// names of the enclosing scopes and this one, joined by '/'
+    pub path: String,
+    pub depth: usize,
+    pub last_ms: f64,
+    /// over the last `window` frames the scope ran in
+    pub average_ms: f64,
+    pub max_ms: f64,
+}
+
+impl GpuProfiler {
+    /// Results are read `latency` frames after they were recorded,
+    /// 2 or 3 is enough to never stall.
+    pub fn new(latency: usize) -> Self {
+        Self {
+            latency: latency.max(1),
+            window: 60,
+            free: Vec::new(),
+            pending: VecDeque::new(),
+            current: Frame::default(),
+            open: Vec::new(),
+            stats: HashMap::new(),
+            order: Vec::new(),
+            trace: VecDeque::new(),
+            max_trace_events: 100_000,
+            epoch: None,
+            frame_index: 0,
+        }
+    }
+
+    /// The number of frames the rolling averages are over.
+    pub fn set_window(&mut self, frames: usize) {
+        self.window = frames.max(1);
+    }
+
+    /// Older events are dropped from the trace beyond this.
+    pub fn set_max_trace_events(&mut self, n: usize) {
+        self.max_trace_events = n;
+        self.trim_trace();
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.current.index = self.frame_index;
+    }
+
+    /// Closes the frame and collects the results of older frames
+    /// that are available.
+    pub fn end_frame(&mut self) {
+        while !self.open.is_empty() {
+            self.end_scope();
+        }
+        let frame = std::mem::take(&mut self.current);
+        self.pending.push_back(frame);
+        self.frame_index += 1;
+        self.current.index = self.frame_index;
+
+        while self.pending.len() > self.latency {
+            let late = self.pending.len() > self.latency * 2;
+            if !late && !matches!(self.pending.front(), Some(frame) if Self::available(frame)) {
+                break;
+            }
+            // reading the results of a late frame waits for them
+            let frame = self.pending.pop_front().unwrap();
+            self.collect(frame);
+        }
+    }
+
+    /// Times everything until the guard drops. Scopes opened on the
+    /// guard are nested in this one.
+    pub fn scope(&mut self, name: &str) -> ProfileScope<'_> {
+        self.begin_scope(name);
+        ProfileScope { profiler: self }
+    }
+
+    pub fn begin_scope(&mut self, name: &str) {
+        let (path, depth) = match self.open.last() {
+            Some(&parent) => {
+                let parent = &self.current.scopes[parent];
+                (format!("{}/{}", parent.path, name), parent.depth + 1)
+            }
+            None => (name.to_string(), 0),
+        };
+        let start = self.query();
+        start.query_counter();
+        self.open.push(self.current.scopes.len());
+        self.current.scopes.push(Scope {
+            name: name.to_string(),
+            path,
+            depth,
+            start,
+            end: None,
+        });
+    }
+
+    pub fn end_scope(&mut self) {
+        if let Some(i) = self.open.pop() {
+            let end = self.query();
+            end.query_counter();
+            self.current.scopes[i].end = Some(end);
+        }
+    }
+
+    /// All scopes seen so far, in the order they first ran.
+    pub fn timings(&self) -> Vec<ScopeTiming> {
+        self.order
+            .iter()
+            .filter_map(|path| {
+                let stats = &self.stats[path];
+                let last_ms = *stats.samples.back()?;
+                Some(ScopeTiming {
+                    name: stats.name.clone(),
+                    path: path.clone(),
+                    depth: stats.depth,
+                    last_ms,
+                    average_ms: stats.samples.iter().sum::<f64>() / stats.samples.len() as f64,
+                    max_ms: stats.samples.iter().cloned().fold(0.0, f64::max),
+                })
+            })
+            .collect()
+    }
+
+    pub fn timing(&self, path: &str) -> Option<ScopeTiming> {
+        self.timings().into_iter().find(|t| t.path == path)
+    }
+
+    /// The recorded scopes in the Chrome trace event format, open it
+    /// with chrome://tracing or https://ui.perfetto.dev.
+    pub fn chrome_trace(&self) -> String {
+        let mut json = String::from("{\"traceEvents\":[");
+        for (i, event) in self.trace.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"name\":\"{}\",\"cat\":\"gpu\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\
+                 \"ts\":{:.3},\"dur\":{:.3},\"args\":{{\"frame\":{},\"depth\":{}}}}}",
+                escape(&event.name),
+                event.start_ns as f64 / 1000.0,
+                event.duration_ns as f64 / 1000.0,
+                event.frame,
+                event.depth,
+            );
+        }
+        json.push_str("],\"displayTimeUnit\":\"ms\"}");
+        json
+    }
+
+    pub fn save_chrome_trace(&self, path: impl AsRef<std::path::Path>) -> Result<(), String> {
+        std::fs::write(path.as_ref(), self.chrome_trace())
+            .map_err(|e| format!("Could not write trace '{:?}': {}", path.as_ref(), e))
+    }
+
+    pub fn clear_trace(&mut self) {
+        self.trace.clear();
+    }
+
+    fn query(&mut self) -> Query {
+        self.free
+            .pop()
+            .unwrap_or_else(|| Query::new(Target::TimeStamp))
+    }
+
+    fn available(frame: &Frame) -> bool {
+        frame
+            .scopes
+            .iter()
+            .filter_map(|s| s.end.as_ref())
+            .all(Query::result_available)
+    }
+
+    fn collect(&mut self, frame: Frame) {
+        for scope in frame.scopes {
+            let Scope {
+                name,
+                path,
+                depth,
+                start,
+                end,
+            } = scope;
+            let end = end.expect("[ERROR] Profiler scope was never ended");
+            let start_ns = start.result();
+            let duration_ns = end.result() - start_ns;
+            let epoch = *self.epoch.get_or_insert(start_ns);
+
+            if !self.stats.contains_key(&path) {
+                self.order.push(path.clone());
+            }
+            let window = self.window;
+            let stats = self.stats.entry(path).or_insert_with(|| Stats {
+                name: name.clone(),
+                depth,
+                samples: VecDeque::new(),
+            });
+            stats.samples.push_back(duration_ns as f64 / 1e6);
+            while stats.samples.len() > window {
+                stats.samples.pop_front();
+            }
+
+            self.trace.push_back(TraceEvent {
+                name,
+                frame: frame.index,
+                depth,
+                start_ns: start_ns - epoch,
+                duration_ns,
+            });
+            self.free.push(start);
+            self.free.push(end);
+        }
+        self.trim_trace();
+    }
+
+    fn trim_trace(&mut self) {
+        while self.trace.len() > self.max_trace_events {
+            self.trace.pop_front();
+        }
+    }
+}
+
+/// Ends its scope when dropped. Nested scopes are opened on the
+/// guard, so they always end before it.
+pub struct ProfileScope<'a> {
+    profiler: &'a mut GpuProfiler,
+}
+
+impl<'a> ProfileScope<'a> {
+    pub fn scope(&mut self, name: &str) -> ProfileScope<'_> {
+        self.profiler.scope(name)
+    }
+}
+
+impl<'a> Drop for ProfileScope<'a> {
+    fn drop(&mut self) {
+        self.profiler.end_scope();
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
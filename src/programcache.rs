@@ -0,0 +1,261 @@
+use std::ffi::CStr;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"PGLB";
+
+/// A directory of linked program binaries, so later runs can skip
+/// compiling and linking. Binaries are keyed by the preprocessed
+/// sources and the driver, a driver update makes them miss, and
+/// binaries the driver rejects are deleted and rebuilt. Files are
+/// named after a hash of the key and store the full key, which is
+/// compared on load, so hash collisions are misses too.
+#[derive(Clone, Debug)]
+pub struct ProgramCache {
+    dir: PathBuf,
+}
+
+impl ProgramCache {
+    /// The directory is made when the first binary is saved.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether the driver can hand out program binaries at all,
+    /// GL 4.1 or ARB_get_program_binary.
+    pub fn is_supported() -> bool {
+        if crate::utils::gl::version() < (4, 1)
+            && !crate::utils::gl::has_extension("GL_ARB_get_program_binary")
+        {
+            return false;
+        }
+        let mut n = 0;
+        unsafe {
+            gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut n);
+        }
+        n > 0
+    }
+
+    /// Deletes all cached binaries.
+    pub fn clear(&self) -> Result<(), String> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(()),
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension() == Some("bin".as_ref()) {
+                std::fs::remove_file(&path)
+                    .map_err(|e| format!("Could not remove '{:?}': {}", path, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A linked program made from the binary, or None when there is
+    /// none, it was made from other sources or the driver rejects it.
+    pub(crate) fn load(&self, key: &CacheKey) -> Option<u32> {
+        let path = self.path(key);
+        let bytes = std::fs::read(&path).ok()?;
+        let (format, binary) = match parse(&bytes, key) {
+            Some(parsed) => parsed,
+            None => {
+                // corrupt or a collision, rebuilt by the caller
+                let _ = std::fs::remove_file(&path);
+                return None;
+            }
+        };
+        unsafe {
+            let id = gl::CreateProgram();
+            gl::ProgramBinary(
+                id,
+                format,
+                binary.as_ptr() as *const std::ffi::c_void,
+                binary.len() as i32,
+            );
+            let mut linked = 0;
+            gl::GetProgramiv(id, gl::LINK_STATUS, &mut linked);
+            if linked == 0 {
+                gl::DeleteProgram(id);
+                let _ = std::fs::remove_file(&path);
+                return None;
+            }
+            Some(id)
+        }
+    }
+
+    /// Saves the binary of a program that was linked with
+    /// GL_PROGRAM_BINARY_RETRIEVABLE_HINT. Failing is not an error,
+    /// the program is just compiled again next time.
+    pub(crate) fn store(&self, key: &CacheKey, program: u32) {
+        let mut len = 0;
+        unsafe {
+            gl::GetProgramiv(program, gl::PROGRAM_BINARY_LENGTH, &mut len);
+        }
+        if len <= 0 {
+            return;
+        }
+        let mut binary = vec![0u8; len as usize];
+        let mut format = 0;
+        let mut written = 0;
+        unsafe {
+            gl::GetProgramBinary(
+                program,
+                len,
+                &mut written,
+                &mut format,
+                binary.as_mut_ptr() as *mut std::ffi::c_void,
+            );
+        }
+        binary.truncate(written.max(0) as usize);
+        if binary.is_empty() {
+            return;
+        }
+        let bytes = encode(key, format, &binary);
+        if std::fs::create_dir_all(&self.dir).is_ok() {
+            // write and rename, so a crash never leaves half a binary
+            let tmp = self.path(key).with_extension("tmp");
+            if std::fs::write(&tmp, &bytes).is_ok() {
+                let _ = std::fs::rename(&tmp, self.path(key));
+            }
+        }
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(format!("{:016x}.bin", key.hash))
+    }
+}
+
+/// Everything a binary depends on: the driver's vendor, renderer and
+/// version strings and the given parts, f.i. the sources.
+pub(crate) struct CacheKey {
+    material: Vec<u8>,
+    hash: u64,
+}
+
+impl CacheKey {
+    pub(crate) fn new(parts: &[&str]) -> Self {
+        let driver = [gl::VENDOR, gl::RENDERER, gl::VERSION]
+            .iter()
+            .map(|name| driver_string(*name))
+            .collect::<Vec<_>>();
+        let mut material = Vec::new();
+        for part in driver
+            .iter()
+            .map(|s| s.as_str())
+            .chain(parts.iter().cloned())
+        {
+            material.extend_from_slice(part.as_bytes());
+            material.push(0);
+        }
+        let mut hash = Fnv::new();
+        hash.write(&material);
+        Self {
+            hash: hash.0,
+            material,
+        }
+    }
+}
+
+// MAGIC, the binary format, the length of the key material, the key
+// material and the binary.
+fn encode(key: &CacheKey, format: u32, binary: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(16 + key.material.len() + binary.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&format.to_le_bytes());
+    bytes.extend_from_slice(&(key.material.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&key.material);
+    bytes.extend_from_slice(binary);
+    bytes
+}
+
+// The binary format and the binary, if the file is intact and was
+// made for `key`.
+fn parse<'a>(bytes: &'a [u8], key: &CacheKey) -> Option<(u32, &'a [u8])> {
+    if bytes.get(..4)? != MAGIC {
+        return None;
+    }
+    let mut format = [0; 4];
+    format.copy_from_slice(bytes.get(4..8)?);
+    let mut key_len = [0; 8];
+    key_len.copy_from_slice(bytes.get(8..16)?);
+    let key_end = (u64::from_le_bytes(key_len) as usize).checked_add(16)?;
+    if bytes.get(16..key_end)? != key.material.as_slice() {
+        return None;
+    }
+    Some((u32::from_le_bytes(format), &bytes[key_end..]))
+}
+
+fn driver_string(name: u32) -> String {
+    unsafe {
+        let s = gl::GetString(name);
+        if s.is_null() {
+            return String::new();
+        }
+        CStr::from_ptr(s as *const _).to_string_lossy().into_owned()
+    }
+}
+
+// FNV-1a, unlike std's hasher it is the same across Rust versions
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // like CacheKey::new, without asking a driver
+    fn key(material: &str) -> CacheKey {
+        let mut hash = Fnv::new();
+        hash.write(material.as_bytes());
+        CacheKey {
+            material: material.as_bytes().to_vec(),
+            hash: hash.0,
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let key = key("vendor\0renderer\0version\0void main() {}\0");
+        let bytes = encode(&key, 0x1234, &[1, 2, 3]);
+        assert_eq!(parse(&bytes, &key), Some((0x1234, &[1u8, 2, 3][..])));
+    }
+
+    #[test]
+    fn other_key_misses() {
+        let bytes = encode(&key("a"), 1, &[1, 2, 3]);
+        assert_eq!(parse(&bytes, &key("b")), None);
+        assert_eq!(parse(&bytes, &key("ab")), None);
+        assert_eq!(parse(&bytes, &key("")), None);
+    }
+
+    #[test]
+    fn corrupt_files_miss() {
+        let key = key("abc");
+        let bytes = encode(&key, 1, &[1, 2, 3]);
+        for len in 0..16 + 3 {
+            assert_eq!(parse(&bytes[..len], &key), None);
+        }
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(parse(&bad_magic, &key), None);
+        let mut huge_len = bytes;
+        huge_len[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(parse(&huge_len, &key), None);
+    }
+}
@@ -1,8 +1,20 @@
 use gl::types::*;
 
+// GL_ARB_pipeline_statistics_query, core in GL 4.6
+const VERTICES_SUBMITTED: GLenum = 0x82EE;
+const PRIMITIVES_SUBMITTED: GLenum = 0x82EF;
+const VERTEX_SHADER_INVOCATIONS: GLenum = 0x82F0;
+const TESS_CONTROL_SHADER_PATCHES: GLenum = 0x82F1;
+const TESS_EVALUATION_SHADER_INVOCATIONS: GLenum = 0x82F2;
+const GEOMETRY_SHADER_PRIMITIVES_EMITTED: GLenum = 0x82F3;
+const FRAGMENT_SHADER_INVOCATIONS: GLenum = 0x82F4;
+const COMPUTE_SHADER_INVOCATIONS: GLenum = 0x82F5;
+const CLIPPING_INPUT_PRIMITIVES: GLenum = 0x82F6;
+const CLIPPING_OUTPUT_PRIMITIVES: GLenum = 0x82F7;
+
 pub struct Query {
     id: GLuint,
-    target: GLenum,
+    target: Target,
 }
 
 impl Query {
@@ -10,19 +22,36 @@ impl Query {
         unsafe {
             let mut id = 0;
             gl::GenQueries(1, &mut id);
-            Self {
-                id,
-                target: target as GLenum,
-            }
+            Self { id, target }
         }
     }
 
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
+    /// Only one query per target can be active at a time. Timestamps
+    /// cannot begin, use `query_counter`.
     pub fn begin(&self) {
-        unsafe { gl::BeginQuery(self.target, self.id) }
+        assert!(
+            self.target != Target::TimeStamp,
+            "[ERROR] Timestamp queries are recorded with query_counter"
+        );
+        unsafe { gl::BeginQuery(self.target as GLenum, self.id) }
     }
     pub fn end(&self) {
-        unsafe { gl::EndQuery(self.target) }
+        unsafe { gl::EndQuery(self.target as GLenum) }
+    }
+    /// Records the gpu time in nanoseconds once all previous commands
+    /// are done, for `TimeStamp` queries.
+    pub fn query_counter(&self) {
+        assert!(
+            self.target == Target::TimeStamp,
+            "[ERROR] Only timestamp queries can record a counter"
+        );
+        unsafe { gl::QueryCounter(self.id, gl::TIMESTAMP) }
     }
+    /// Blocks until the result is available.
     pub fn result(&self) -> i64 {
         unsafe {
             let mut res = 0;
@@ -30,6 +59,14 @@ impl Query {
             res
         }
     }
+    /// The result if it is available, without blocking.
+    pub fn try_result(&self) -> Option<i64> {
+        if self.result_available() {
+            Some(self.result())
+        } else {
+            None
+        }
+    }
     pub fn result_available(&self) -> bool {
         unsafe {
             let mut res = 0;
@@ -37,6 +74,17 @@ impl Query {
             res == 1
         }
     }
+
+    /// Draw calls until the guard drops are discarded by the gpu when
+    /// this occlusion query counted no samples (or, inverted, some).
+    pub fn conditional_render(&self, mode: ConditionalMode) -> ConditionalRender {
+        assert!(
+            self.target.is_occlusion(),
+            "[ERROR] Conditional rendering needs an occlusion query"
+        );
+        unsafe { gl::BeginConditionalRender(self.id, mode as GLenum) }
+        ConditionalRender { _private: () }
+    }
 }
 
 impl Drop for Query {
@@ -46,7 +94,111 @@ impl Drop for Query {
 }
 
 #[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Target {
+    /// nanoseconds between begin and end
     TimeElapsed = gl::TIME_ELAPSED,
+    /// see `Query::query_counter`
     TimeStamp = gl::TIMESTAMP,
+    /// samples that passed the depth and stencil tests
+    SamplesPassed = gl::SAMPLES_PASSED,
+    /// 1 if any sample passed
+    AnySamplesPassed = gl::ANY_SAMPLES_PASSED,
+    /// like AnySamplesPassed, but may report false positives, faster
+    AnySamplesPassedConservative = gl::ANY_SAMPLES_PASSED_CONSERVATIVE,
+    PrimitivesGenerated = gl::PRIMITIVES_GENERATED,
+    TransformFeedbackPrimitivesWritten = gl::TRANSFORM_FEEDBACK_PRIMITIVES_WRITTEN,
+    // pipeline statistics, see `Target::is_supported`
+    VerticesSubmitted = VERTICES_SUBMITTED,
+    PrimitivesSubmitted = PRIMITIVES_SUBMITTED,
+    VertexShaderInvocations = VERTEX_SHADER_INVOCATIONS,
+    TessControlShaderPatches = TESS_CONTROL_SHADER_PATCHES,
+    TessEvaluationShaderInvocations = TESS_EVALUATION_SHADER_INVOCATIONS,
+    GeometryShaderInvocations = gl::GEOMETRY_SHADER_INVOCATIONS,
+    GeometryShaderPrimitivesEmitted = GEOMETRY_SHADER_PRIMITIVES_EMITTED,
+    FragmentShaderInvocations = FRAGMENT_SHADER_INVOCATIONS,
+    ComputeShaderInvocations = COMPUTE_SHADER_INVOCATIONS,
+    ClippingInputPrimitives = CLIPPING_INPUT_PRIMITIVES,
+    ClippingOutputPrimitives = CLIPPING_OUTPUT_PRIMITIVES,
+}
+
+impl Target {
+    pub fn is_occlusion(&self) -> bool {
+        matches!(
+            self,
+            Self::SamplesPassed | Self::AnySamplesPassed | Self::AnySamplesPassedConservative
+        )
+    }
+
+    pub fn is_pipeline_statistic(&self) -> bool {
+        (VERTICES_SUBMITTED..=CLIPPING_OUTPUT_PRIMITIVES).contains(&(*self as GLenum))
+            || *self == Self::GeometryShaderInvocations
+    }
+
+    /// Whether the current context has this target, from its GL
+    /// version or the extension that added it.
+    pub fn is_supported(&self) -> bool {
+        let (version, extension) = match self {
+            Self::TimeElapsed | Self::TimeStamp => ((3, 3), "GL_ARB_timer_query"),
+            Self::AnySamplesPassed => ((3, 3), "GL_ARB_occlusion_query2"),
+            Self::AnySamplesPassedConservative => ((4, 3), "GL_ARB_ES3_compatibility"),
+            Self::SamplesPassed
+            | Self::PrimitivesGenerated
+            | Self::TransformFeedbackPrimitivesWritten => return true,
+            // pipeline statistics
+            _ => ((4, 6), "GL_ARB_pipeline_statistics_query"),
+        };
+        supported(version, extension)
+    }
+}
+
+fn supported(version: (u32, u32), extension: &str) -> bool {
+    crate::utils::gl::version() >= version || crate::utils::gl::has_extension(extension)
+}
+
+/// How `Query::conditional_render` waits for the query result.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConditionalMode {
+    /// waits for the result
+    Wait = gl::QUERY_WAIT,
+    /// renders anyway when the result is not available yet
+    NoWait = gl::QUERY_NO_WAIT,
+    /// may only wait for the part of the result of the same region
+    ByRegionWait = gl::QUERY_BY_REGION_WAIT,
+    ByRegionNoWait = gl::QUERY_BY_REGION_NO_WAIT,
+    /// the inverted modes render when no samples passed, GL 4.5
+    WaitInverted = gl::QUERY_WAIT_INVERTED,
+    NoWaitInverted = gl::QUERY_NO_WAIT_INVERTED,
+    ByRegionWaitInverted = gl::QUERY_BY_REGION_WAIT_INVERTED,
+    ByRegionNoWaitInverted = gl::QUERY_BY_REGION_NO_WAIT_INVERTED,
+}
+
+impl ConditionalMode {
+    pub fn is_inverted(&self) -> bool {
+        matches!(
+            self,
+            Self::WaitInverted
+                | Self::NoWaitInverted
+                | Self::ByRegionWaitInverted
+                | Self::ByRegionNoWaitInverted
+        )
+    }
+
+    /// The inverted modes need GL 4.5 or ARB_conditional_render_inverted.
+    pub fn is_supported(&self) -> bool {
+        !self.is_inverted() || supported((4, 5), "GL_ARB_conditional_render_inverted")
+    }
+}
+
+/// Ends conditional rendering when dropped.
+#[must_use = "conditional rendering ends when the guard drops"]
+pub struct ConditionalRender {
+    _private: (),
+}
+
+impl Drop for ConditionalRender {
+    fn drop(&mut self) {
+        unsafe { gl::EndConditionalRender() }
+    }
 }
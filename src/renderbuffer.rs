@@ -0,0 +1,62 @@
+use crate::texture::Format;
+
+/// Render target storage that cannot be sampled, f.i. for
+/// a depth/stencil buffer that is only used while rendering.
+pub struct Renderbuffer {
+    id: u32,
+    format: Format,
+    size: (u32, u32),
+    samples: u32,
+}
+
+impl Renderbuffer {
+    pub fn new(format: Format, size: (u32, u32)) -> Self {
+        Self::multisampled(format, size, 0)
+    }
+
+    /// With `samples` > 0 the storage is multisampled, it then has
+    /// to be resolved (see `FrameBuffer::resolve_to`) before use.
+    pub fn multisampled(format: Format, (width, height): (u32, u32), samples: u32) -> Self {
+        let mut id = 0;
+        unsafe {
+            gl::GenRenderbuffers(1, &mut id);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, id);
+            gl::RenderbufferStorageMultisample(
+                gl::RENDERBUFFER,
+                samples as i32,
+                format.internal(),
+                width as i32,
+                height as i32,
+            );
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+        }
+        Self {
+            id,
+            format,
+            size: (width, height),
+            samples,
+        }
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub(crate) fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for Renderbuffer {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteRenderbuffers(1, &self.id) }
+    }
+}
@@ -0,0 +1,493 @@
+use crate::framebuffer::Rect;
+pub use crate::sampler::CompareFunc;
+
+/// Draw buffers with their own blend state, GL guarantees 8.
+/// Before GL 4.0 they can only be enabled separately, every draw
+/// buffer uses the blend function of draw buffer 0.
+pub const MAX_BLEND_ATTACHMENTS: usize = 8;
+
+/// All fixed function state that affects a draw call. Build one per
+/// kind of pass and apply it with a `RenderStateTracker`, which only
+/// makes the GL calls for what changed since the last state.
+///
+/// The default is GL's initial state.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderState {
+    /// None disables blending for that draw buffer
+    pub blend: [Option<Blend>; MAX_BLEND_ATTACHMENTS],
+    pub blend_color: [f32; 4],
+    /// None disables the depth test (and so depth writes)
+    pub depth: Option<Depth>,
+    /// None disables the stencil test
+    pub stencil: Option<Stencil>,
+    /// None disables culling
+    pub cull: Option<Face>,
+    pub front_face: Winding,
+    pub polygon_mode: PolygonMode,
+    /// None disables the offset, for all polygon modes
+    pub polygon_offset: Option<PolygonOffset>,
+    /// rgba
+    pub color_mask: [bool; 4],
+    /// None disables the scissor test
+    pub scissor: Option<Rect>,
+    /// restarts primitives at the largest index value, before GL 4.3
+    /// at 0xFFFFFFFF, so only for u32 indices
+    pub primitive_restart: bool,
+    /// linear to srgb conversion when writing to srgb attachments
+    pub srgb: bool,
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        Self {
+            blend: [None; MAX_BLEND_ATTACHMENTS],
+            blend_color: [0.0; 4],
+            depth: None,
+            stencil: None,
+            cull: None,
+            front_face: Winding::CounterClockwise,
+            polygon_mode: PolygonMode::Fill,
+            polygon_offset: None,
+            color_mask: [true; 4],
+            scissor: None,
+            primitive_restart: false,
+            srgb: false,
+        }
+    }
+}
+
+impl RenderState {
+    /// The same blend state for every draw buffer.
+    pub fn blend(mut self, blend: Option<Blend>) -> Self {
+        self.blend = [blend; MAX_BLEND_ATTACHMENTS];
+        self
+    }
+
+    pub fn blend_at(mut self, draw_buffer: usize, blend: Option<Blend>) -> Self {
+        assert!(
+            draw_buffer < MAX_BLEND_ATTACHMENTS,
+            "[ERROR] Only draw buffers 0 to 7 have their own blend state"
+        );
+        self.blend[draw_buffer] = blend;
+        self
+    }
+
+    pub fn depth(mut self, depth: Option<Depth>) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn stencil(mut self, stencil: Option<Stencil>) -> Self {
+        self.stencil = stencil;
+        self
+    }
+
+    pub fn cull(mut self, cull: Option<Face>, front_face: Winding) -> Self {
+        self.cull = cull;
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn polygon_mode(mut self, mode: PolygonMode) -> Self {
+        self.polygon_mode = mode;
+        self
+    }
+
+    pub fn polygon_offset(mut self, offset: Option<PolygonOffset>) -> Self {
+        self.polygon_offset = offset;
+        self
+    }
+
+    pub fn color_mask(mut self, r: bool, g: bool, b: bool, a: bool) -> Self {
+        self.color_mask = [r, g, b, a];
+        self
+    }
+
+    pub fn scissor(mut self, scissor: Option<Rect>) -> Self {
+        self.scissor = scissor;
+        self
+    }
+
+    pub fn primitive_restart(mut self, primitive_restart: bool) -> Self {
+        self.primitive_restart = primitive_restart;
+        self
+    }
+
+    pub fn srgb(mut self, srgb: bool) -> Self {
+        self.srgb = srgb;
+        self
+    }
+
+    /// Sets everything, whatever the current GL state is.
+    pub fn apply_all(&self) {
+        apply(self, None, crate::utils::gl::version());
+    }
+}
+
+/// Applies render states, skipping the GL calls for parts that
+/// equal the previously applied state.
+#[derive(Default)]
+pub struct RenderStateTracker {
+    current: Option<RenderState>,
+    // of the context, queried on the first apply
+    version: Option<(u32, u32)>,
+}
+
+impl RenderStateTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply(&mut self, state: &RenderState) {
+        let version = *self.version.get_or_insert_with(crate::utils::gl::version);
+        apply(state, self.current.as_ref(), version);
+        self.current = Some(*state);
+    }
+
+    /// Forgets the applied state, f.i. after GL calls made elsewhere
+    /// or when another context is made current, the next `apply` sets
+    /// everything.
+    pub fn invalidate(&mut self) {
+        self.current = None;
+        self.version = None;
+    }
+
+    pub fn current(&self) -> Option<&RenderState> {
+        self.current.as_ref()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Blend {
+    pub color: BlendEquation,
+    pub alpha: BlendEquation,
+}
+
+impl Blend {
+    pub fn new(op: BlendOp, src: BlendFactor, dst: BlendFactor) -> Self {
+        let equation = BlendEquation { op, src, dst };
+        Self {
+            color: equation,
+            alpha: equation,
+        }
+    }
+
+    /// Regular transparency, src over dst.
+    pub fn alpha() -> Self {
+        Self::new(
+            BlendOp::Add,
+            BlendFactor::SrcAlpha,
+            BlendFactor::OneMinusSrcAlpha,
+        )
+    }
+
+    /// For colors already multiplied by their alpha.
+    pub fn premultiplied() -> Self {
+        Self::new(
+            BlendOp::Add,
+            BlendFactor::One,
+            BlendFactor::OneMinusSrcAlpha,
+        )
+    }
+
+    pub fn additive() -> Self {
+        Self::new(BlendOp::Add, BlendFactor::One, BlendFactor::One)
+    }
+}
+
+/// result = op(src * src_factor, dst * dst_factor)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlendEquation {
+    pub op: BlendOp,
+    pub src: BlendFactor,
+    pub dst: BlendFactor,
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendOp {
+    Add = gl::FUNC_ADD,
+    Subtract = gl::FUNC_SUBTRACT,
+    ReverseSubtract = gl::FUNC_REVERSE_SUBTRACT,
+    /// ignores the factors
+    Min = gl::MIN,
+    /// ignores the factors
+    Max = gl::MAX,
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendFactor {
+    Zero = gl::ZERO,
+    One = gl::ONE,
+    SrcColor = gl::SRC_COLOR,
+    OneMinusSrcColor = gl::ONE_MINUS_SRC_COLOR,
+    DstColor = gl::DST_COLOR,
+    OneMinusDstColor = gl::ONE_MINUS_DST_COLOR,
+    SrcAlpha = gl::SRC_ALPHA,
+    OneMinusSrcAlpha = gl::ONE_MINUS_SRC_ALPHA,
+    DstAlpha = gl::DST_ALPHA,
+    OneMinusDstAlpha = gl::ONE_MINUS_DST_ALPHA,
+    /// the `blend_color` of the state
+    ConstantColor = gl::CONSTANT_COLOR,
+    OneMinusConstantColor = gl::ONE_MINUS_CONSTANT_COLOR,
+    ConstantAlpha = gl::CONSTANT_ALPHA,
+    OneMinusConstantAlpha = gl::ONE_MINUS_CONSTANT_ALPHA,
+    SrcAlphaSaturate = gl::SRC_ALPHA_SATURATE,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Depth {
+    pub func: CompareFunc,
+    pub write: bool,
+}
+
+impl Depth {
+    pub fn new(func: CompareFunc, write: bool) -> Self {
+        Self { func, write }
+    }
+}
+
+impl Default for Depth {
+    fn default() -> Self {
+        Self::new(CompareFunc::Less, true)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Stencil {
+    pub front: StencilFace,
+    pub back: StencilFace,
+}
+
+impl Stencil {
+    /// The same for front and back facing polygons.
+    pub fn both(face: StencilFace) -> Self {
+        Self {
+            front: face,
+            back: face,
+        }
+    }
+}
+
+/// Passes when `func(reference & read_mask, stored & read_mask)`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StencilFace {
+    pub func: CompareFunc,
+    pub reference: i32,
+    pub read_mask: u32,
+    pub write_mask: u32,
+    /// when the stencil test fails
+    pub fail: StencilOp,
+    /// when the stencil test passes, but the depth test fails
+    pub depth_fail: StencilOp,
+    /// when both pass
+    pub pass: StencilOp,
+}
+
+impl Default for StencilFace {
+    fn default() -> Self {
+        Self {
+            func: CompareFunc::Always,
+            reference: 0,
+            read_mask: !0,
+            write_mask: !0,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StencilOp {
+    Keep = gl::KEEP,
+    Zero = gl::ZERO,
+    Replace = gl::REPLACE,
+    Incr = gl::INCR,
+    IncrWrap = gl::INCR_WRAP,
+    Decr = gl::DECR,
+    DecrWrap = gl::DECR_WRAP,
+    Invert = gl::INVERT,
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Face {
+    Front = gl::FRONT,
+    Back = gl::BACK,
+    FrontAndBack = gl::FRONT_AND_BACK,
+}
+
+/// The vertex order of front facing triangles.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Winding {
+    CounterClockwise = gl::CCW,
+    Clockwise = gl::CW,
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PolygonMode {
+    Fill = gl::FILL,
+    Line = gl::LINE,
+    Point = gl::POINT,
+}
+
+/// depth += factor * slope + units * smallest depth difference
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PolygonOffset {
+    pub factor: f32,
+    pub units: f32,
+}
+
+/// Sets the parts of `new` that differ from `old`, all of them
+/// without an old state.
+fn apply(new: &RenderState, old: Option<&RenderState>, version: (u32, u32)) {
+    let changed = |part: &dyn Fn(&RenderState) -> bool| match old {
+        Some(old) => part(old),
+        None => true,
+    };
+    // the gl crate panics on functions the driver did not load,
+    // `version` decides which ones are used
+    unsafe {
+        for i in 0..MAX_BLEND_ATTACHMENTS {
+            if !changed(&|old| old.blend[i] != new.blend[i]) {
+                continue;
+            }
+            match new.blend[i] {
+                Some(Blend { color, alpha }) if version >= (4, 0) => {
+                    gl::Enablei(gl::BLEND, i as u32);
+                    gl::BlendEquationSeparatei(i as u32, color.op as u32, alpha.op as u32);
+                    gl::BlendFuncSeparatei(
+                        i as u32,
+                        color.src as u32,
+                        color.dst as u32,
+                        alpha.src as u32,
+                        alpha.dst as u32,
+                    );
+                }
+                Some(Blend { color, alpha }) => {
+                    gl::Enablei(gl::BLEND, i as u32);
+                    if i == 0 {
+                        gl::BlendEquationSeparate(color.op as u32, alpha.op as u32);
+                        gl::BlendFuncSeparate(
+                            color.src as u32,
+                            color.dst as u32,
+                            alpha.src as u32,
+                            alpha.dst as u32,
+                        );
+                    }
+                }
+                None => gl::Disablei(gl::BLEND, i as u32),
+            }
+        }
+        if changed(&|old| old.blend_color != new.blend_color) {
+            let [r, g, b, a] = new.blend_color;
+            gl::BlendColor(r, g, b, a);
+        }
+
+        if changed(&|old| old.depth != new.depth) {
+            match new.depth {
+                Some(depth) => {
+                    gl::Enable(gl::DEPTH_TEST);
+                    gl::DepthFunc(depth.func as u32);
+                    gl::DepthMask(depth.write as u8);
+                }
+                None => {
+                    gl::Disable(gl::DEPTH_TEST);
+                    // a disabled test writes nothing, but the mask also
+                    // applies to clearing the depth buffer
+                    gl::DepthMask(gl::TRUE);
+                }
+            }
+        }
+
+        if changed(&|old| old.stencil != new.stencil) {
+            match new.stencil {
+                Some(stencil) => {
+                    gl::Enable(gl::STENCIL_TEST);
+                    for (face, s) in [(gl::FRONT, stencil.front), (gl::BACK, stencil.back)] {
+                        gl::StencilFuncSeparate(face, s.func as u32, s.reference, s.read_mask);
+                        gl::StencilMaskSeparate(face, s.write_mask);
+                        gl::StencilOpSeparate(
+                            face,
+                            s.fail as u32,
+                            s.depth_fail as u32,
+                            s.pass as u32,
+                        );
+                    }
+                }
+                None => gl::Disable(gl::STENCIL_TEST),
+            }
+        }
+
+        if changed(&|old| old.cull != new.cull) {
+            match new.cull {
+                Some(face) => {
+                    gl::Enable(gl::CULL_FACE);
+                    gl::CullFace(face as u32);
+                }
+                None => gl::Disable(gl::CULL_FACE),
+            }
+        }
+        if changed(&|old| old.front_face != new.front_face) {
+            gl::FrontFace(new.front_face as u32);
+        }
+        if changed(&|old| old.polygon_mode != new.polygon_mode) {
+            gl::PolygonMode(gl::FRONT_AND_BACK, new.polygon_mode as u32);
+        }
+
+        if changed(&|old| old.polygon_offset != new.polygon_offset) {
+            let caps = [
+                gl::POLYGON_OFFSET_FILL,
+                gl::POLYGON_OFFSET_LINE,
+                gl::POLYGON_OFFSET_POINT,
+            ];
+            match new.polygon_offset {
+                Some(offset) => {
+                    caps.iter().for_each(|cap| gl::Enable(*cap));
+                    gl::PolygonOffset(offset.factor, offset.units);
+                }
+                None => caps.iter().for_each(|cap| gl::Disable(*cap)),
+            }
+        }
+
+        if changed(&|old| old.color_mask != new.color_mask) {
+            let [r, g, b, a] = new.color_mask;
+            gl::ColorMask(r as u8, g as u8, b as u8, a as u8);
+        }
+
+        if changed(&|old| old.scissor != new.scissor) {
+            match new.scissor {
+                Some((x, y, w, h)) => {
+                    gl::Enable(gl::SCISSOR_TEST);
+                    gl::Scissor(x as i32, y as i32, w as i32, h as i32);
+                }
+                None => gl::Disable(gl::SCISSOR_TEST),
+            }
+        }
+
+        if changed(&|old| old.primitive_restart != new.primitive_restart) {
+            if version >= (4, 3) {
+                set_cap(gl::PRIMITIVE_RESTART_FIXED_INDEX, new.primitive_restart);
+            } else {
+                gl::PrimitiveRestartIndex(u32::MAX);
+                set_cap(gl::PRIMITIVE_RESTART, new.primitive_restart);
+            }
+        }
+        if changed(&|old| old.srgb != new.srgb) {
+            set_cap(gl::FRAMEBUFFER_SRGB, new.srgb);
+        }
+    }
+}
+
+unsafe fn set_cap(cap: u32, enabled: bool) {
+    if enabled {
+        gl::Enable(cap);
+    } else {
+        gl::Disable(cap);
+    }
+}
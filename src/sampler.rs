@@ -1,5 +1,11 @@
+use crate::state;
+use crate::texture::{Filtering, Options, Wrapping};
 use gl::types::*;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 
+/// A sampler object overrides the sampling parameters of
+/// whatever texture is bound to the same unit.
 pub struct Sampler {
     id: GLuint,
 }
@@ -12,10 +18,65 @@ impl Sampler {
             Self { id }
         }
     }
-    pub fn bind_to(&self, slot: u32) {
-        unsafe {
-            gl::BindSampler(slot, self.id);
+
+    pub fn from_descriptor(desc: &SamplerDescriptor) -> Self {
+        let sampler = Self::new();
+        sampler.set_descriptor(desc);
+        sampler
+    }
+
+    pub fn set_descriptor(&self, desc: &SamplerDescriptor) {
+        self.set_i(Param::WrapS, desc.wrap_s.gl() as i32);
+        self.set_i(Param::WrapT, desc.wrap_t.gl() as i32);
+        self.set_i(Param::WrapR, desc.wrap_r.gl() as i32);
+        // always written, so a reused sampler keeps nothing of an
+        // earlier descriptor
+        self.set_fv(Param::BorderColor, &desc.border_color().unwrap_or([0.0; 4]));
+        self.set_i(
+            Param::MinFilter,
+            desc.min_filter.gl_min(desc.mipmap_filter) as i32,
+        );
+        self.set_i(Param::MagFilter, desc.mag_filter.gl() as i32);
+        self.set_f(Param::MinLod, desc.min_lod);
+        self.set_f(Param::MaxLod, desc.max_lod);
+        self.set_f(Param::LodBias, desc.lod_bias);
+        // drivers without EXT_texture_filter_anisotropic raise an error
+        let max_anisotropy = max_anisotropy();
+        if max_anisotropy > 1.0 {
+            let anisotropy = desc.max_anisotropy.max(1.0).min(max_anisotropy);
+            self.set_f(Param::MaxAnisotropy, anisotropy);
         }
+        match desc.compare {
+            Some(func) => {
+                self.set_i(Param::CompareMode, gl::COMPARE_REF_TO_TEXTURE as i32);
+                self.set_i(Param::CompareFunc, func as i32);
+            }
+            None => self.set_i(Param::CompareMode, gl::NONE as i32),
+        }
+    }
+
+    pub fn set_i(&self, param: Param, value: i32) {
+        unsafe { gl::SamplerParameteri(self.id, param as GLenum, value) }
+    }
+
+    pub fn set_f(&self, param: Param, value: f32) {
+        unsafe { gl::SamplerParameterf(self.id, param as GLenum, value) }
+    }
+
+    pub fn set_fv(&self, param: Param, values: &[f32]) {
+        unsafe { gl::SamplerParameterfv(self.id, param as GLenum, values.as_ptr()) }
+    }
+
+    pub fn bind_to(&self, slot: u32) {
+        state::bind_sampler(slot, self.id);
+    }
+
+    pub fn unbind_from(slot: u32) {
+        state::bind_sampler(slot, 0);
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
     }
 }
 
@@ -24,16 +85,198 @@ impl Drop for Sampler {
         unsafe {
             gl::DeleteSamplers(1, &self.id);
         }
+        state::deleted_sampler(self.id);
     }
 }
 
 #[repr(u32)]
+#[derive(Copy, Clone, Debug)]
 pub enum Param {
     WrapS = gl::TEXTURE_WRAP_S,
     WrapT = gl::TEXTURE_WRAP_T,
     WrapR = gl::TEXTURE_WRAP_R,
     MinFilter = gl::TEXTURE_MIN_FILTER,
     MagFilter = gl::TEXTURE_MAG_FILTER,
+    MinLod = gl::TEXTURE_MIN_LOD,
+    MaxLod = gl::TEXTURE_MAX_LOD,
+    LodBias = gl::TEXTURE_LOD_BIAS,
+    MaxAnisotropy = TEXTURE_MAX_ANISOTROPY,
+    CompareMode = gl::TEXTURE_COMPARE_MODE,
+    CompareFunc = gl::TEXTURE_COMPARE_FUNC,
+    BorderColor = gl::TEXTURE_BORDER_COLOR,
 }
 
-// TODO: FINSIH
+/// Depth comparison for shadow samplers (`sampler2DShadow`).
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CompareFunc {
+    Never = gl::NEVER,
+    Less = gl::LESS,
+    Equal = gl::EQUAL,
+    LessEqual = gl::LEQUAL,
+    Greater = gl::GREATER,
+    NotEqual = gl::NOTEQUAL,
+    GreaterEqual = gl::GEQUAL,
+    Always = gl::ALWAYS,
+}
+
+/// Everything a sampler can be configured with. Two equal
+/// descriptors produce identical samplers, see `SamplerCache`.
+#[derive(Copy, Clone, Debug)]
+pub struct SamplerDescriptor {
+    pub wrap_s: Wrapping,
+    pub wrap_t: Wrapping,
+    pub wrap_r: Wrapping,
+    pub min_filter: Filtering,
+    pub mag_filter: Filtering,
+    /// None disables mipmapping
+    pub mipmap_filter: Option<Filtering>,
+    pub min_lod: f32,
+    pub max_lod: f32,
+    pub lod_bias: f32,
+    /// 1.0 means no anisotropic filtering, higher values are
+    /// clamped to what EXT_texture_filter_anisotropic allows
+    pub max_anisotropy: f32,
+    /// Some enables depth comparison, for shadow sampling
+    pub compare: Option<CompareFunc>,
+}
+
+impl Default for SamplerDescriptor {
+    fn default() -> Self {
+        Self {
+            wrap_s: Wrapping::Repeat,
+            wrap_t: Wrapping::Repeat,
+            wrap_r: Wrapping::Repeat,
+            min_filter: Filtering::Linear,
+            mag_filter: Filtering::Linear,
+            mipmap_filter: Some(Filtering::Linear),
+            min_lod: -1000.0,
+            max_lod: 1000.0,
+            lod_bias: 0.0,
+            max_anisotropy: 1.0,
+            compare: None,
+        }
+    }
+}
+
+impl SamplerDescriptor {
+    pub fn wrapping(mut self, wrapping: Wrapping) -> Self {
+        self.wrap_s = wrapping;
+        self.wrap_t = wrapping;
+        self.wrap_r = wrapping;
+        self
+    }
+
+    pub fn filtering(mut self, filtering: Filtering) -> Self {
+        self.min_filter = filtering;
+        self.mag_filter = filtering;
+        self
+    }
+
+    /// A clamped, linearly filtered depth comparison sampler.
+    pub fn shadow(func: CompareFunc) -> Self {
+        Self {
+            mipmap_filter: None,
+            compare: Some(func),
+            ..Self::default()
+        }
+        .wrapping(Wrapping::ClampEdge)
+    }
+
+    /// GL has a single border colour per sampler, so the
+    /// first `Wrapping::Constant` axis decides it.
+    pub fn border_color(&self) -> Option<[f32; 4]> {
+        [self.wrap_s, self.wrap_t, self.wrap_r]
+            .iter()
+            .find_map(|w| w.border_color())
+    }
+
+    // floats are compared by their bits, so the
+    // descriptor can be used as a hashmap key
+    fn key(&self) -> Vec<u32> {
+        let mut key = Vec::with_capacity(16);
+        for w in [self.wrap_s, self.wrap_t, self.wrap_r].iter() {
+            key.push(w.gl());
+        }
+        if let Some(c) = self.border_color() {
+            key.extend(c.iter().map(|f| f.to_bits()));
+        }
+        key.push(self.min_filter.gl_min(self.mipmap_filter));
+        key.push(self.mag_filter.gl());
+        key.push(self.min_lod.to_bits());
+        key.push(self.max_lod.to_bits());
+        key.push(self.lod_bias.to_bits());
+        key.push(self.max_anisotropy.to_bits());
+        key.push(self.compare.map_or(gl::NONE, |f| f as u32));
+        key
+    }
+}
+
+impl PartialEq for SamplerDescriptor {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDescriptor {}
+
+impl Hash for SamplerDescriptor {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl From<&Options> for SamplerDescriptor {
+    fn from(options: &Options) -> Self {
+        Self {
+            wrap_s: options.wrap_s,
+            wrap_t: options.wrap_t,
+            min_filter: options.min_filter,
+            mag_filter: options.mag_filter,
+            mipmap_filter: options.mipmap_filter(),
+            max_anisotropy: options.max_anisotropy,
+            ..Self::default()
+        }
+    }
+}
+
+/// Hands out one sampler per distinct descriptor, so
+/// materials asking for the same sampling share it.
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: HashMap<SamplerDescriptor, Sampler>,
+}
+
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self, desc: &SamplerDescriptor) -> &Sampler {
+        self.samplers
+            .entry(*desc)
+            .or_insert_with(|| Sampler::from_descriptor(desc))
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.samplers.clear();
+    }
+}
+
+// EXT_texture_filter_anisotropic, core only since 4.6
+pub(crate) const TEXTURE_MAX_ANISOTROPY: u32 = 0x84FE;
+pub(crate) const MAX_TEXTURE_MAX_ANISOTROPY: u32 = 0x84FF;
+
+/// The largest anisotropy the driver supports, 1.0 if unsupported.
+/// Queried once per context.
+pub fn max_anisotropy() -> f32 {
+    state::max_anisotropy()
+}
@@ -1,11 +1,19 @@
+// Toggles of single capabilities, superseded by `renderstate`: a
+// `RenderStateTracker` does not see these calls and skips setting
+// what it thinks is already set.
+#![allow(deprecated)]
+
+#[deprecated(note = "use a renderstate::RenderState and a RenderStateTracker")]
 pub fn enable(s: &[Option]) {
     s.iter().for_each(|e| e.enable())
 }
 
+#[deprecated(note = "use a renderstate::RenderState and a RenderStateTracker")]
 pub fn disable(s: &[Option]) {
     s.iter().for_each(|e| e.disable())
 }
 
+#[deprecated(note = "use a renderstate::RenderState and a RenderStateTracker")]
 #[repr(u32)]
 #[derive(Copy, Clone)]
 pub enum Option {
@@ -1,3 +1,5 @@
+use crate::programcache::{CacheKey, ProgramCache};
+use crate::state;
 use gl::types::*;
 use std::collections::HashMap;
 use std::ffi::CString;
@@ -6,6 +8,10 @@ use std::path::Path;
 pub struct ShaderOptions {
     pub vs_defines: Vec<String>, 
     pub fs_defines: Vec<String>,
+    /// vertex shader outputs to capture with transform feedback
+    pub feedback: Option<FeedbackVaryings>,
+    /// where to save the linked binary and load it from on later runs
+    pub cache: Option<ProgramCache>,
 }
 
 impl Default for ShaderOptions {
@@ -13,10 +19,38 @@ impl Default for ShaderOptions {
         Self {
             vs_defines: Vec::new(),
             fs_defines: Vec::new(),
+            feedback: None,
+            cache: None,
         }
     }
 }
 
+/// The outputs a program writes to its transform feedback buffers,
+/// set before linking.
+#[derive(Clone, Debug)]
+pub struct FeedbackVaryings {
+    pub names: Vec<String>,
+    pub mode: FeedbackMode,
+}
+
+impl FeedbackVaryings {
+    pub fn new(names: &[&str], mode: FeedbackMode) -> Self {
+        Self {
+            names: names.iter().map(|n| n.to_string()).collect(),
+            mode,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FeedbackMode {
+    /// all varyings into the buffer at index 0, one vertex after another
+    Interleaved = gl::INTERLEAVED_ATTRIBS,
+    /// varying n into the buffer at index n
+    Separate = gl::SEPARATE_ATTRIBS,
+}
+
 pub struct ShaderProgram {
     id: GLuint,
     loc_cache: HashMap<String, GLint>,
@@ -30,6 +64,31 @@ impl ShaderProgram {
         Self::from_frag_and_vert_structs(fs, vs)
     }
 
+    /// A program that captures vertex shader outputs, f.i. to update
+    /// particles on the gpu. Without a fragment shader nothing can be
+    /// rasterized, see `feedback::set_rasterizer_discard`.
+    pub fn with_feedback(
+        fs: Option<&str>,
+        vs: &str,
+        feedback: &FeedbackVaryings,
+    ) -> Result<Self, String> {
+        let mut shaders = vec![Shader::from_source(vs, gl::VERTEX_SHADER)?];
+        if let Some(fs) = fs {
+            shaders.push(Shader::from_source(fs, gl::FRAGMENT_SHADER)?);
+        }
+        Self::link(&shaders, Some(feedback), false)
+    }
+
+    /// Loads the program from `cache` if this driver linked the same
+    /// sources before, otherwise compiles and saves it there.
+    pub fn from_frag_and_vert_src_cached(
+        fs: &str,
+        vs: &str,
+        cache: &ProgramCache,
+    ) -> Result<Self, String> {
+        Self::from_cache(fs, vs, None, cache)
+    }
+
     pub fn from_frag_and_vert_path(
         fs: impl AsRef<Path>,
         vs: impl AsRef<Path>,
@@ -72,14 +131,83 @@ impl ShaderProgram {
         let fragment_src = Shader::unroll_includes(fragment_src, path_base);
         let fragment_src = Self::add_defines(&fragment_src, &options.fs_defines);
 
-        Self::from_frag_and_vert_src(&fragment_src, &vertex_src)
+        match (&options.cache, &options.feedback) {
+            (Some(cache), feedback) => {
+                Self::from_cache(&fragment_src, &vertex_src, feedback.as_ref(), cache)
+            }
+            (None, Some(feedback)) => {
+                Self::with_feedback(Some(&fragment_src), &vertex_src, feedback)
+            }
+            (None, None) => Self::from_frag_and_vert_src(&fragment_src, &vertex_src),
+        }
     }
 
     fn from_frag_and_vert_structs(fs: Shader, vs: Shader) -> Result<Self, String> {
+        Self::link(&[fs, vs], None, false)
+    }
+
+    // falls back to compiling when there is no binary, the driver
+    // changed or it rejects the binary
+    fn from_cache(
+        fs: &str,
+        vs: &str,
+        feedback: Option<&FeedbackVaryings>,
+        cache: &ProgramCache,
+    ) -> Result<Self, String> {
+        let key = if ProgramCache::is_supported() {
+            let feedback_key = format!("{:?}", feedback);
+            Some(CacheKey::new(&[vs, fs, &feedback_key]))
+        } else {
+            None
+        };
+        if let Some(id) = key.as_ref().and_then(|key| cache.load(key)) {
+            let loc_cache = HashMap::new();
+            return Ok(Self { id, loc_cache });
+        }
+
+        let shaders = [
+            Shader::from_source(vs, gl::VERTEX_SHADER)?,
+            Shader::from_source(fs, gl::FRAGMENT_SHADER)?,
+        ];
+        let program = Self::link(&shaders, feedback, key.is_some())?;
+        if let Some(key) = &key {
+            cache.store(key, program.id);
+        }
+        Ok(program)
+    }
+
+    fn link(
+        shaders: &[Shader],
+        feedback: Option<&FeedbackVaryings>,
+        retrievable: bool,
+    ) -> Result<Self, String> {
+        let names = feedback
+            .map(|feedback| {
+                feedback
+                    .names
+                    .iter()
+                    .map(|n| CString::new(n.as_str()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()
+            .map_err(|_| String::from("[ERROR] Feedback varying name contains a nul byte"))?;
         unsafe {
             let id = gl::CreateProgram();
-            gl::AttachShader(id, fs.id);
-            gl::AttachShader(id, vs.id);
+            for shader in shaders {
+                gl::AttachShader(id, shader.id);
+            }
+            if let (Some(feedback), Some(names)) = (feedback, names) {
+                let ptrs = names.iter().map(|n| n.as_ptr()).collect::<Vec<_>>();
+                gl::TransformFeedbackVaryings(
+                    id,
+                    ptrs.len() as i32,
+                    ptrs.as_ptr(),
+                    feedback.mode as GLenum,
+                );
+            }
+            if retrievable {
+                gl::ProgramParameteri(id, gl::PROGRAM_BINARY_RETRIEVABLE_HINT, gl::TRUE as i32);
+            }
             gl::LinkProgram(id);
             // ERROR HANDLING
             let mut it_worked = gl::FALSE as gl::types::GLint;
@@ -101,8 +229,9 @@ impl ShaderProgram {
                 return Err(error);
             }
             // END ERROR HANDLING
-            gl::DetachShader(id, fs.id);
-            gl::DetachShader(id, vs.id);
+            for shader in shaders {
+                gl::DetachShader(id, shader.id);
+            }
 
             let loc_cache = HashMap::new();
 
@@ -111,9 +240,12 @@ impl ShaderProgram {
     }
 
     pub fn bind(&self) {
-        unsafe {
-            gl::UseProgram(self.id);
-        }
+        state::use_program(self.id);
+    }
+
+    /// Binds until the guard drops, then the previous program.
+    pub fn bind_scoped(&self) -> state::Scoped {
+        state::scoped_program(self.id)
     }
 
     pub fn get_location(&mut self, name: &str) -> i32 {
@@ -139,6 +271,27 @@ impl ShaderProgram {
         }
     }
 
+    /// Like `bind_uniform_block`, for a `buffer` block (GL 4.3).
+    pub fn bind_storage_block(&self, name: &str, binding: usize) -> Result<(), &'static str> {
+        if crate::utils::gl::version() < (4, 3)
+            && !crate::utils::gl::has_extension("GL_ARB_shader_storage_buffer_object")
+        {
+            return Err("Shader storage blocks need GL 4.3");
+        }
+        unsafe {
+            let index = gl::GetProgramResourceIndex(
+                self.id,
+                gl::SHADER_STORAGE_BLOCK,
+                const_char_ptr!(name),
+            );
+            if index == gl::INVALID_INDEX {
+                return Err("The program has no storage block of that name");
+            }
+            gl::ShaderStorageBlockBinding(self.id, index, binding as u32);
+        }
+        Ok(())
+    }
+
     pub fn set_uint(&mut self, name: &str, uint: u32) {
         unsafe {
             let loc = self.get_location(name);
@@ -217,6 +370,7 @@ impl Drop for ShaderProgram {
         unsafe {
             gl::DeleteProgram(self.id);
         }
+        state::deleted_program(self.id);
     }
 }
 
@@ -0,0 +1,513 @@
+// A cache of the GL bindings of the current context, so binding
+// what is already bound skips the GL call. Every bind in this crate
+// goes through here, except the copy read/write buffer targets
+// which are only used internally and always reset to 0.
+//
+// The cache is per thread, like the current GL context. After
+// binding objects with raw GL calls, call `invalidate`.
+//
+// `Scoped` guards bind something and restore what was bound before
+// when dropped, so nested passes do not clobber each other's state.
+
+use crate::framebuffer::Rect;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+#[derive(Default)]
+struct State {
+    // None is unknown, the next bind is never skipped
+    program: Option<u32>,
+    vao: Option<u32>,
+    framebuffer: Option<u32>,
+    read_framebuffer: Option<u32>,
+    buffers: HashMap<u32, u32>,
+    active_unit: Option<u32>,
+    // (unit, target) -> texture
+    textures: HashMap<(u32, u32), u32>,
+    // unit -> sampler
+    samplers: HashMap<u32, u32>,
+    max_texture_units: Option<u32>,
+    max_anisotropy: Option<f32>,
+    counters: BindCounters,
+}
+
+/// How many binds of each kind reached GL and how many were skipped
+/// because the object was already bound.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BindCounters {
+    pub program: Counter,
+    pub vertex_array: Counter,
+    pub framebuffer: Counter,
+    pub buffer: Counter,
+    /// texture and sampler binds and active unit switches
+    pub texture: Counter,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Counter {
+    pub issued: u64,
+    pub skipped: u64,
+}
+
+impl Counter {
+    fn count(&mut self, skip: bool) -> bool {
+        if skip {
+            self.skipped += 1;
+        } else {
+            self.issued += 1;
+        }
+        !skip
+    }
+}
+
+impl BindCounters {
+    pub fn issued(&self) -> u64 {
+        self.all().iter().map(|c| c.issued).sum()
+    }
+
+    pub fn skipped(&self) -> u64 {
+        self.all().iter().map(|c| c.skipped).sum()
+    }
+
+    fn all(&self) -> [Counter; 5] {
+        [
+            self.program,
+            self.vertex_array,
+            self.framebuffer,
+            self.buffer,
+            self.texture,
+        ]
+    }
+}
+
+/// The counters since the last `reset_counters`, f.i. call both once
+/// per frame to see how many binds a frame saves.
+pub fn counters() -> BindCounters {
+    STATE.with(|s| s.borrow().counters)
+}
+
+pub fn reset_counters() {
+    STATE.with(|s| s.borrow_mut().counters = BindCounters::default());
+}
+
+/// GL_MAX_COMBINED_TEXTURE_IMAGE_UNITS of the current context.
+pub(crate) fn max_texture_units() -> u32 {
+    STATE.with(|s| {
+        *s.borrow_mut()
+            .max_texture_units
+            .get_or_insert_with(|| get(gl::MAX_COMBINED_TEXTURE_IMAGE_UNITS))
+    })
+}
+
+/// GL_MAX_TEXTURE_MAX_ANISOTROPY of the current context, 1.0
+/// without EXT_texture_filter_anisotropic.
+pub(crate) fn max_anisotropy() -> f32 {
+    STATE.with(|s| {
+        *s.borrow_mut().max_anisotropy.get_or_insert_with(|| {
+            if !crate::utils::gl::has_extension("GL_EXT_texture_filter_anisotropic")
+                && !crate::utils::gl::has_extension("GL_ARB_texture_filter_anisotropic")
+            {
+                return 1.0;
+            }
+            let mut max = 1.0;
+            unsafe { gl::GetFloatv(crate::sampler::MAX_TEXTURE_MAX_ANISOTROPY, &mut max) };
+            max
+        })
+    })
+}
+
+/// Forgets all cached bindings, needed after binding with raw
+/// GL calls or when another context is made current.
+pub fn invalidate() {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let counters = s.counters;
+        *s = State {
+            counters,
+            ..State::default()
+        };
+    });
+}
+
+fn get(query: u32) -> u32 {
+    let mut value = 0;
+    unsafe { gl::GetIntegerv(query, &mut value) };
+    value as u32
+}
+
+impl State {
+    fn program(&mut self) -> u32 {
+        *self.program.get_or_insert_with(|| get(gl::CURRENT_PROGRAM))
+    }
+
+    fn vao(&mut self) -> u32 {
+        *self
+            .vao
+            .get_or_insert_with(|| get(gl::VERTEX_ARRAY_BINDING))
+    }
+
+    fn framebuffer(&mut self) -> u32 {
+        *self
+            .framebuffer
+            .get_or_insert_with(|| get(gl::DRAW_FRAMEBUFFER_BINDING))
+    }
+
+    fn read_framebuffer(&mut self) -> u32 {
+        *self
+            .read_framebuffer
+            .get_or_insert_with(|| get(gl::READ_FRAMEBUFFER_BINDING))
+    }
+
+    fn active_unit(&mut self) -> u32 {
+        *self
+            .active_unit
+            .get_or_insert_with(|| get(gl::ACTIVE_TEXTURE) - gl::TEXTURE0)
+    }
+
+    /// Of the active unit.
+    fn texture(&mut self, target: u32) -> u32 {
+        let unit = self.active_unit();
+        *self
+            .textures
+            .entry((unit, target))
+            .or_insert_with(|| get(texture_binding(target)))
+    }
+}
+
+// The query for what is bound to `target`.
+fn texture_binding(target: u32) -> u32 {
+    match target {
+        gl::TEXTURE_1D => gl::TEXTURE_BINDING_1D,
+        gl::TEXTURE_1D_ARRAY => gl::TEXTURE_BINDING_1D_ARRAY,
+        gl::TEXTURE_2D => gl::TEXTURE_BINDING_2D,
+        gl::TEXTURE_2D_ARRAY => gl::TEXTURE_BINDING_2D_ARRAY,
+        gl::TEXTURE_2D_MULTISAMPLE => gl::TEXTURE_BINDING_2D_MULTISAMPLE,
+        gl::TEXTURE_2D_MULTISAMPLE_ARRAY => gl::TEXTURE_BINDING_2D_MULTISAMPLE_ARRAY,
+        gl::TEXTURE_3D => gl::TEXTURE_BINDING_3D,
+        gl::TEXTURE_CUBE_MAP => gl::TEXTURE_BINDING_CUBE_MAP,
+        gl::TEXTURE_CUBE_MAP_ARRAY => gl::TEXTURE_BINDING_CUBE_MAP_ARRAY,
+        gl::TEXTURE_RECTANGLE => gl::TEXTURE_BINDING_RECTANGLE,
+        gl::TEXTURE_BUFFER => gl::TEXTURE_BINDING_BUFFER,
+        _ => panic!("[ERROR] Unknown texture target {:#x}", target),
+    }
+}
+
+pub(crate) fn use_program(id: u32) {
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if s.counters.program.count(s.program == Some(id)) {
+            unsafe { gl::UseProgram(id) }
+            s.program = Some(id);
+        }
+    });
+}
+
+pub(crate) fn bind_vertex_array(id: u32) {
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if s.counters.vertex_array.count(s.vao == Some(id)) {
+            unsafe { gl::BindVertexArray(id) }
+            s.vao = Some(id);
+            // the index buffer binding is part of the vertex array
+            s.buffers.remove(&gl::ELEMENT_ARRAY_BUFFER);
+        }
+    });
+}
+
+/// Binds to GL_FRAMEBUFFER, so for both drawing and reading.
+pub(crate) fn bind_framebuffer(id: u32) {
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        let bound = s.framebuffer == Some(id) && s.read_framebuffer == Some(id);
+        if s.counters.framebuffer.count(bound) {
+            unsafe { gl::BindFramebuffer(gl::FRAMEBUFFER, id) }
+            s.framebuffer = Some(id);
+            s.read_framebuffer = Some(id);
+        }
+    });
+}
+
+// Restores separate draw and read bindings.
+fn bind_framebuffers(draw: u32, read: u32) {
+    if draw == read {
+        return bind_framebuffer(draw);
+    }
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        unsafe {
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, draw);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, read);
+        }
+        s.counters.framebuffer.count(false);
+        s.framebuffer = Some(draw);
+        s.read_framebuffer = Some(read);
+    });
+}
+
+pub(crate) fn bind_buffer(target: u32, id: u32) {
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        let bound = s.buffers.get(&target) == Some(&id);
+        if s.counters.buffer.count(bound) {
+            unsafe { gl::BindBuffer(target, id) }
+            s.buffers.insert(target, id);
+        }
+    });
+}
+
+/// glBindBufferBase/Range also bind to the generic target.
+pub(crate) fn bound_indexed(target: u32, id: u32) {
+    STATE.with(|s| {
+        s.borrow_mut().buffers.insert(target, id);
+    });
+}
+
+/// Binds to `unit`, leaving that unit active.
+pub(crate) fn bind_texture(unit: u32, target: u32, id: u32) {
+    active_texture(unit);
+    bind_texture_active(target, id);
+}
+
+/// Binds to whichever unit is active, like glBindTexture.
+pub(crate) fn bind_texture_active(target: u32, id: u32) {
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        let unit = s.active_unit();
+        let bound = s.textures.get(&(unit, target)) == Some(&id);
+        if s.counters.texture.count(bound) {
+            unsafe { gl::BindTexture(target, id) }
+            s.textures.insert((unit, target), id);
+        }
+    });
+}
+
+/// Binds to `unit`, then activates the previously active unit again.
+pub(crate) fn bind_texture_to(unit: u32, target: u32, id: u32) {
+    let active_unit = STATE.with(|s| s.borrow_mut().active_unit());
+    bind_texture(unit, target, id);
+    active_texture(active_unit);
+}
+
+/// The texture bound to `unit`, None if unknown. Never asks GL.
+pub(crate) fn bound_texture(unit: u32, target: u32) -> Option<u32> {
+    STATE.with(|s| s.borrow().textures.get(&(unit, target)).copied())
+}
+
+/// The sampler bound to `unit`, None if unknown. Never asks GL.
+pub(crate) fn bound_sampler(unit: u32) -> Option<u32> {
+    STATE.with(|s| s.borrow().samplers.get(&unit).copied())
+}
+
+pub(crate) fn bind_sampler(unit: u32, id: u32) {
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        let bound = s.samplers.get(&unit) == Some(&id);
+        if s.counters.texture.count(bound) {
+            unsafe { gl::BindSampler(unit, id) }
+            s.samplers.insert(unit, id);
+        }
+    });
+}
+
+pub(crate) fn active_texture(unit: u32) {
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if s.counters.texture.count(s.active_unit == Some(unit)) {
+            unsafe { gl::ActiveTexture(gl::TEXTURE0 + unit) }
+            s.active_unit = Some(unit);
+        }
+    });
+}
+
+// Deleting a bound object binds 0 in its place, except for the
+// current program, which stays in use until another one is.
+
+pub(crate) fn deleted_program(id: u32) {
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if s.program == Some(id) {
+            s.program = None;
+        }
+    });
+}
+
+pub(crate) fn deleted_vertex_array(id: u32) {
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if s.vao == Some(id) {
+            s.vao = Some(0);
+            s.buffers.remove(&gl::ELEMENT_ARRAY_BUFFER);
+        }
+    });
+}
+
+pub(crate) fn deleted_framebuffer(id: u32) {
+    STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        if s.framebuffer == Some(id) {
+            s.framebuffer = Some(0);
+        }
+        if s.read_framebuffer == Some(id) {
+            s.read_framebuffer = Some(0);
+        }
+    });
+}
+
+pub(crate) fn deleted_buffer(id: u32) {
+    STATE.with(|s| {
+        s.borrow_mut()
+            .buffers
+            .values_mut()
+            .filter(|bound| **bound == id)
+            .for_each(|bound| *bound = 0);
+    });
+}
+
+pub(crate) fn deleted_texture(id: u32) {
+    STATE.with(|s| {
+        s.borrow_mut()
+            .textures
+            .values_mut()
+            .filter(|bound| **bound == id)
+            .for_each(|bound| *bound = 0);
+    });
+}
+
+pub(crate) fn deleted_sampler(id: u32) {
+    STATE.with(|s| {
+        s.borrow_mut()
+            .samplers
+            .values_mut()
+            .filter(|bound| **bound == id)
+            .for_each(|bound| *bound = 0);
+    });
+}
+
+/// Restores a binding (or viewport, or scissor) when dropped.
+#[must_use = "the previous state is restored when the guard drops"]
+pub struct Scoped {
+    restore: Restore,
+}
+
+enum Restore {
+    Program(u32),
+    VertexArray(u32),
+    Framebuffer {
+        draw: u32,
+        read: u32,
+    },
+    Texture {
+        active_unit: u32,
+        unit: u32,
+        target: u32,
+        id: u32,
+    },
+    Viewport([i32; 4]),
+    Scissor(bool, [i32; 4]),
+}
+
+impl Drop for Scoped {
+    fn drop(&mut self) {
+        match self.restore {
+            Restore::Program(id) => use_program(id),
+            Restore::VertexArray(id) => bind_vertex_array(id),
+            Restore::Framebuffer { draw, read } => bind_framebuffers(draw, read),
+            Restore::Texture {
+                active_unit,
+                unit,
+                target,
+                id,
+            } => {
+                bind_texture(unit, target, id);
+                active_texture(active_unit);
+            }
+            Restore::Viewport([x, y, w, h]) => unsafe { gl::Viewport(x, y, w, h) },
+            Restore::Scissor(enabled, [x, y, w, h]) => unsafe {
+                gl::Scissor(x, y, w, h);
+                if enabled {
+                    gl::Enable(gl::SCISSOR_TEST);
+                } else {
+                    gl::Disable(gl::SCISSOR_TEST);
+                }
+            },
+        }
+    }
+}
+
+fn scoped(restore: Restore) -> Scoped {
+    Scoped { restore }
+}
+
+pub(crate) fn scoped_program(id: u32) -> Scoped {
+    let previous = STATE.with(|s| s.borrow_mut().program());
+    use_program(id);
+    scoped(Restore::Program(previous))
+}
+
+pub(crate) fn scoped_vertex_array(id: u32) -> Scoped {
+    let previous = STATE.with(|s| s.borrow_mut().vao());
+    bind_vertex_array(id);
+    scoped(Restore::VertexArray(previous))
+}
+
+/// Binds for drawing and reading, both bindings are restored.
+pub(crate) fn scoped_framebuffer(id: u32) -> Scoped {
+    let (draw, read) = STATE.with(|s| {
+        let s = &mut *s.borrow_mut();
+        (s.framebuffer(), s.read_framebuffer())
+    });
+    bind_framebuffer(id);
+    scoped(Restore::Framebuffer { draw, read })
+}
+
+/// Binds to `unit`, the active unit is restored too.
+pub(crate) fn scoped_texture(unit: u32, target: u32, id: u32) -> Scoped {
+    let active_unit = STATE.with(|s| s.borrow_mut().active_unit());
+    active_texture(unit);
+    let previous = STATE.with(|s| s.borrow_mut().texture(target));
+    bind_texture_active(target, id);
+    scoped(Restore::Texture {
+        active_unit,
+        unit,
+        target,
+        id: previous,
+    })
+}
+
+/// Binds to the active unit, f.i. to upload data.
+pub(crate) fn scoped_texture_active(target: u32, id: u32) -> Scoped {
+    let unit = STATE.with(|s| s.borrow_mut().active_unit());
+    scoped_texture(unit, target, id)
+}
+
+/// Sets the viewport (x, y, w, h) until the guard drops.
+pub fn scoped_viewport((x, y, w, h): Rect) -> Scoped {
+    let mut previous = [0; 4];
+    unsafe {
+        gl::GetIntegerv(gl::VIEWPORT, previous.as_mut_ptr());
+        gl::Viewport(x as i32, y as i32, w as i32, h as i32);
+    }
+    scoped(Restore::Viewport(previous))
+}
+
+/// Sets the scissor test until the guard drops, None disables it.
+pub fn scoped_scissor(scissor: Option<Rect>) -> Scoped {
+    let mut previous = [0; 4];
+    let enabled = unsafe {
+        gl::GetIntegerv(gl::SCISSOR_BOX, previous.as_mut_ptr());
+        let enabled = gl::IsEnabled(gl::SCISSOR_TEST) == gl::TRUE;
+        match scissor {
+            Some((x, y, w, h)) => {
+                gl::Enable(gl::SCISSOR_TEST);
+                gl::Scissor(x as i32, y as i32, w as i32, h as i32);
+            }
+            None => gl::Disable(gl::SCISSOR_TEST),
+        }
+        enabled
+    };
+    scoped(Restore::Scissor(enabled, previous))
+}
@@ -0,0 +1,293 @@
+use crate::buffer::{Buffer, BufferType, DrawType, MapFlags, PersistentBuffer, Pod};
+use crate::layout::{self, BlockField, Layout};
+use crate::sync::FencedQueue;
+
+/// A range handed out by `StreamBuffer`, in bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StreamRange {
+    pub offset: usize,
+    pub size: usize,
+}
+
+enum Storage {
+    /// Mapped for its whole life, in flight ranges are protected
+    /// by a fence per frame.
+    Persistent(PersistentBuffer<u8>),
+    /// Reallocated when the ring wraps, the driver keeps the old
+    /// storage alive for as long as the gpu reads it. Only wraps
+    /// between frames, so one frame's ranges must fit in the ring.
+    Orphaning(Buffer),
+}
+
+/// Suballocates per-frame data (uniforms, vertices) from one large
+/// ring, so data the gpu may still be reading is never overwritten.
+/// Call `end_frame` after the draws using this frame's ranges have
+/// been issued, ranges are recycled once that frame is done.
+pub struct StreamBuffer {
+    storage: Storage,
+    ring: Ring,
+    frame_bytes: usize,
+    frames: FencedQueue<usize>,
+}
+
+// Where the next range goes, apart from the storage and the fences.
+#[derive(Debug)]
+struct Ring {
+    capacity: usize,
+    alignment: usize,
+    head: usize,
+    // bytes taken by ranges (and wrap padding) not yet recycled
+    used: usize,
+}
+
+impl Ring {
+    fn new(capacity: usize, alignment: usize) -> Self {
+        Self {
+            capacity,
+            alignment,
+            head: 0,
+            used: 0,
+        }
+    }
+
+    // The offset of the next range of `size` bytes, the bytes it
+    // takes including the padding skipped over, and whether it wraps.
+    fn place(&self, size: usize) -> (usize, usize, bool) {
+        let offset = layout::round_up(self.head, self.alignment);
+        if offset + size <= self.capacity {
+            (offset, offset - self.head + size, false)
+        } else {
+            (0, self.capacity - self.head + size, true)
+        }
+    }
+
+    fn fits(&self, needed: usize) -> bool {
+        self.used + needed <= self.capacity
+    }
+
+    fn take(&mut self, offset: usize, size: usize, needed: usize) {
+        self.head = offset + size;
+        self.used += needed;
+    }
+
+    fn free(&mut self, bytes: usize) {
+        self.used -= bytes;
+        // nothing is in use, so start over instead of wrapping later
+        if self.used == 0 {
+            self.head = 0;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.head = 0;
+        self.used = 0;
+    }
+}
+
+impl StreamBuffer {
+    /// Persistent mapping when available (GL 4.4+), otherwise orphaning.
+    pub fn new(buffertype: BufferType, capacity: usize) -> Self {
+        Self::persistent(buffertype, capacity)
+            .unwrap_or_else(|_| Self::orphaning(buffertype, capacity))
+    }
+
+    pub fn persistent(buffertype: BufferType, capacity: usize) -> Result<Self, &'static str> {
+        let buffer = PersistentBuffer::new(buffertype, capacity, true)?;
+        let alignment = buffer.buffer().offset_alignment();
+        Ok(Self::with_storage(
+            Storage::Persistent(buffer),
+            capacity,
+            alignment,
+        ))
+    }
+
+    pub fn orphaning(buffertype: BufferType, capacity: usize) -> Self {
+        let mut buffer = Buffer::new(buffertype, DrawType::Stream);
+        buffer.bind();
+        buffer.init(capacity);
+        buffer.unbind();
+        let alignment = buffer.offset_alignment();
+        Self::with_storage(Storage::Orphaning(buffer), capacity, alignment)
+    }
+
+    fn with_storage(storage: Storage, capacity: usize, alignment: usize) -> Self {
+        Self {
+            storage,
+            // vertex data still wants 4 byte alignment
+            ring: Ring::new(capacity, alignment.max(4)),
+            frame_bytes: 0,
+            frames: FencedQueue::new(),
+        }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        match &self.storage {
+            Storage::Persistent(buffer) => buffer.buffer(),
+            Storage::Orphaning(buffer) => buffer,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity
+    }
+
+    /// Every range offset is a multiple of this, at least
+    /// `GL_UNIFORM_BUFFER_OFFSET_ALIGNMENT` for uniform buffers.
+    pub fn alignment(&self) -> usize {
+        self.ring.alignment
+    }
+
+    /// Copies `content` into a fresh range of the ring. When the ring
+    /// is full this blocks until the oldest frame is done.
+    pub fn push<T: Pod>(&mut self, content: &[T]) -> Result<StreamRange, &'static str> {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                content.as_ptr() as *const u8,
+                std::mem::size_of_val(content),
+            )
+        };
+        let range = self.allocate(bytes.len())?;
+        self.write(range, bytes)?;
+        Ok(range)
+    }
+
+    /// Pushes a block value with std140 padding, for uniform blocks.
+    pub fn push_block<T: BlockField>(&mut self, value: &T) -> Result<StreamRange, &'static str> {
+        self.push(&layout::to_bytes(value, Layout::Std140))
+    }
+
+    /// Binds a range to an indexed binding point, see
+    /// `Buffer::set_binding_range`.
+    pub fn bind_range(&self, binding: usize, range: StreamRange) {
+        self.buffer()
+            .set_binding_range(binding, range.offset, range.size);
+    }
+
+    /// Marks the end of this frame's ranges.
+    pub fn end_frame(&mut self) {
+        if self.frame_bytes > 0 {
+            self.frames.push(self.frame_bytes);
+            self.frame_bytes = 0;
+        }
+        // never blocks, so never fails
+        let _ = self.recycle(false);
+    }
+
+    fn allocate(&mut self, size: usize) -> Result<StreamRange, &'static str> {
+        if size == 0 || size > self.ring.capacity {
+            return Err("Stream range does not fit in the ring");
+        }
+        loop {
+            let (offset, needed, wraps) = self.ring.place(size);
+            if wraps {
+                if let Storage::Orphaning(buffer) = &mut self.storage {
+                    // draws of this frame's ranges may not be issued yet and
+                    // would read the new storage, so only orphan between frames
+                    if self.frame_bytes > 0 {
+                        return Err("Stream ring is full for this frame, make it larger");
+                    }
+                    buffer.orphan();
+                    // the gpu keeps the old storage, nothing is in use
+                    self.frames = FencedQueue::new();
+                    self.ring.reset();
+                    continue;
+                }
+            }
+            if self.ring.fits(needed) {
+                self.ring.take(offset, size, needed);
+                self.frame_bytes += needed;
+                return Ok(StreamRange { offset, size });
+            }
+            if self.frames.is_empty() {
+                return Err("Stream ring is full, call end_frame more often");
+            }
+            // freeing may move the head, so place the range again
+            self.recycle(true)?;
+        }
+    }
+
+    fn write(&mut self, range: StreamRange, bytes: &[u8]) -> Result<(), &'static str> {
+        match &mut self.storage {
+            Storage::Persistent(buffer) => {
+                buffer.write(range.offset, bytes);
+            }
+            Storage::Orphaning(buffer) => {
+                // the range was never handed out since the last
+                // orphaning, which only happens between frames, so
+                // the gpu cannot be reading it
+                let mut mapped = buffer.map_range::<u8>(
+                    range.offset,
+                    range.size,
+                    MapFlags::WRITE | MapFlags::INVALIDATE_RANGE | MapFlags::UNSYNCHRONIZED,
+                )?;
+                mapped.write(0, bytes);
+            }
+        }
+        Ok(())
+    }
+
+    /// Frees the ranges of finished frames, with `block` at least
+    /// the oldest frame is waited for.
+    fn recycle(&mut self, block: bool) -> Result<(), &'static str> {
+        if block {
+            if let Some(bytes) = self.frames.pop_wait() {
+                let bytes = bytes.map_err(|_| "Waiting for the gpu to finish a frame failed")?;
+                self.ring.free(bytes);
+            }
+        }
+        while let Some(bytes) = self.frames.pop_signaled() {
+            self.ring.free(bytes);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // allocates like StreamBuffer::allocate, without storage or fences
+    fn push(ring: &mut Ring, size: usize) -> Option<usize> {
+        let (offset, needed, _) = ring.place(size);
+        if !ring.fits(needed) {
+            return None;
+        }
+        ring.take(offset, size, needed);
+        Some(needed)
+    }
+
+    #[test]
+    fn aligns_offsets() {
+        let mut ring = Ring::new(256, 16);
+        assert_eq!(push(&mut ring, 4), Some(4));
+        assert_eq!(ring.place(4), (16, 16, false));
+        assert_eq!(push(&mut ring, 4), Some(16));
+        assert_eq!(ring.used, 20);
+    }
+
+    #[test]
+    fn wrap_counts_padding() {
+        let mut ring = Ring::new(100, 4);
+        let first = push(&mut ring, 60).unwrap();
+        let second = push(&mut ring, 20).unwrap();
+        ring.free(first);
+        // the 20 bytes at the end are skipped and stay used until freed
+        assert_eq!(ring.place(30), (0, 50, true));
+        let third = push(&mut ring, 30).unwrap();
+        assert_eq!(ring.used, second + third);
+        assert_eq!(push(&mut ring, 30), None);
+        ring.free(second);
+        assert_eq!(push(&mut ring, 30), Some(32));
+    }
+
+    #[test]
+    fn empty_ring_starts_over() {
+        let mut ring = Ring::new(100, 4);
+        let first = push(&mut ring, 60).unwrap();
+        ring.free(first);
+        assert_eq!(ring.head, 0);
+        assert_eq!(push(&mut ring, 70), Some(70));
+        assert_eq!(ring.place(30), (0, 60, true));
+        assert_eq!(push(&mut ring, 30), None);
+    }
+}
@@ -0,0 +1,185 @@
+use gl::types::*;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// A fence is signaled once the GPU finished all commands
+/// issued before it was made. Deleted when dropped.
+pub struct Fence {
+    sync: GLsync,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WaitStatus {
+    Signaled,
+    TimedOut,
+    /// glClientWaitSync failed, f.i. the context was lost
+    Failed,
+}
+
+impl Fence {
+    /// Inserts the fence after all commands issued so far.
+    pub fn new() -> Self {
+        let sync = unsafe { gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0) };
+        Self { sync }
+    }
+
+    /// Does not block.
+    pub fn signaled(&self) -> bool {
+        self.wait_timeout(Duration::from_secs(0)) == WaitStatus::Signaled
+    }
+
+    /// Blocks for at most `timeout`. Flushes, so the fence is sure
+    /// to be signaled eventually.
+    pub fn wait_timeout(&self, timeout: Duration) -> WaitStatus {
+        let nanos = timeout.as_nanos().min(u64::MAX as u128) as u64;
+        let status = unsafe { gl::ClientWaitSync(self.sync, gl::SYNC_FLUSH_COMMANDS_BIT, nanos) };
+        match status {
+            gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => WaitStatus::Signaled,
+            gl::TIMEOUT_EXPIRED => WaitStatus::TimedOut,
+            _ => WaitStatus::Failed,
+        }
+    }
+
+    /// Blocks until the fence is signaled, or the wait failed.
+    #[must_use = "a failed wait does not mean the gpu is done"]
+    pub fn wait(&self) -> WaitStatus {
+        loop {
+            match self.wait_timeout(Duration::from_secs(1)) {
+                WaitStatus::TimedOut => continue,
+                status => return status,
+            }
+        }
+    }
+
+    /// Makes the gpu (not the cpu) wait for the fence before running
+    /// commands issued after this call, returns immediately. Only
+    /// useful across contexts sharing objects, within one context
+    /// commands already run in order.
+    pub fn gpu_wait(&self) {
+        unsafe {
+            gl::Flush();
+            gl::WaitSync(self.sync, 0, gl::TIMEOUT_IGNORED);
+        }
+    }
+}
+
+impl Default for Fence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Fence {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteSync(self.sync) }
+    }
+}
+
+/// Values that are released in order once the gpu is done with
+/// the commands issued before they were pushed, f.i. buffer ranges
+/// or readbacks of a frame.
+pub struct FencedQueue<T> {
+    queue: VecDeque<(Fence, T)>,
+}
+
+impl<T> Default for FencedQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> FencedQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Inserts a fence after all commands issued so far.
+    pub fn push(&mut self, value: T) {
+        self.queue.push_back((Fence::new(), value));
+    }
+
+    /// The oldest value if its fence is signaled, without blocking.
+    pub fn pop_signaled(&mut self) -> Option<T> {
+        if !self.queue.front()?.0.signaled() {
+            return None;
+        }
+        self.queue.pop_front().map(|(_, value)| value)
+    }
+
+    /// The oldest value, blocks until its fence is signaled. A failed
+    /// wait (f.i. a lost context) leaves the value queued and is an Err.
+    pub fn pop_wait(&mut self) -> Option<Result<T, WaitStatus>> {
+        let status = self.queue.front()?.0.wait();
+        if status != WaitStatus::Signaled {
+            return Some(Err(status));
+        }
+        self.queue.pop_front().map(|(_, value)| Ok(value))
+    }
+
+    /// The oldest value, blocks at most `timeout` for its fence.
+    pub fn pop_timeout(&mut self, timeout: Duration) -> Option<T> {
+        if self.queue.front()?.0.wait_timeout(timeout) != WaitStatus::Signaled {
+            return None;
+        }
+        self.queue.pop_front().map(|(_, value)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// Limits how many frames the cpu runs ahead of the gpu. Call
+/// `end_frame` after submitting (before swapping buffers), it blocks
+/// while more than `max_in_flight` frames are unfinished.
+pub struct FramePacer {
+    frames: FencedQueue<()>,
+    max_in_flight: usize,
+}
+
+impl FramePacer {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            frames: FencedQueue::new(),
+            max_in_flight: max_in_flight.max(1),
+        }
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frames.push(());
+        while self.frames.pop_signaled().is_some() {}
+        while self.frames.len() > self.max_in_flight {
+            if let Some(Err(_)) = self.frames.pop_wait() {
+                // the gpu cannot be waited for, pacing is pointless
+                self.frames = FencedQueue::new();
+            }
+        }
+    }
+
+    /// Frames submitted but not yet finished by the gpu.
+    pub fn n_in_flight(&mut self) -> usize {
+        while self.frames.pop_signaled().is_some() {}
+        self.frames.len()
+    }
+
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+
+    /// Blocks until the gpu finished every frame, or waiting failed.
+    pub fn wait_idle(&mut self) -> WaitStatus {
+        while let Some(popped) = self.frames.pop_wait() {
+            if let Err(status) = popped {
+                self.frames = FencedQueue::new();
+                return status;
+            }
+        }
+        WaitStatus::Signaled
+    }
+}
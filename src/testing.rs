@@ -0,0 +1,352 @@
+// Helpers for render regression tests: render offscreen, then
+// compare the result with a reference image.
+//
+// With the `headless` feature the context comes from EGL and needs
+// no display, f.i. Mesa's llvmpipe on CI. Without it a hidden GLFW
+// window is used, which needs a display server (or `xvfb-run`) and
+// must be driven from a single thread, so run those tests with
+// `cargo test -- --test-threads=1`.
+
+use crate::framebuffer::FrameBuffer;
+use crate::texture::Format;
+use image::{Rgba, RgbaImage};
+use std::path::{Path, PathBuf};
+
+/// When set, missing or mismatching references are (over)written
+/// with the rendered image instead of failing.
+pub const UPDATE_REFERENCES_VAR: &str = "PGL_UPDATE_REFERENCES";
+
+#[derive(Copy, Clone, Debug)]
+pub struct Tolerance {
+    /// largest difference per channel for a pixel to still match
+    pub per_channel: u8,
+    /// percentage (0-100) of pixels that may mismatch
+    pub max_mismatch_percent: f32,
+    /// if Some, the SSIM of the images must be at least this (0-1)
+    pub min_ssim: Option<f32>,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            per_channel: 2,
+            max_mismatch_percent: 0.1,
+            min_ssim: None,
+        }
+    }
+}
+
+pub struct Comparison {
+    pub mismatched: usize,
+    pub mismatch_percent: f32,
+    pub max_difference: u8,
+    pub ssim: f32,
+    /// mismatching pixels in red over a faded copy of the expected image
+    pub diff: RgbaImage,
+}
+
+impl Comparison {
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.mismatch_percent <= tolerance.max_mismatch_percent
+            && !matches!(tolerance.min_ssim, Some(min) if self.ssim < min)
+    }
+}
+
+pub fn compare(
+    actual: &RgbaImage,
+    expected: &RgbaImage,
+    tolerance: &Tolerance,
+) -> Result<Comparison, String> {
+    if actual.dimensions() != expected.dimensions() {
+        return Err(format!(
+            "Image sizes differ: actual {:?}, expected {:?}",
+            actual.dimensions(),
+            expected.dimensions()
+        ));
+    }
+    let (w, h) = actual.dimensions();
+    let mut diff = RgbaImage::new(w, h);
+    let mut mismatched = 0;
+    let mut max_difference = 0;
+    for (x, y, a) in actual.enumerate_pixels() {
+        let e = expected.get_pixel(x, y);
+        let difference =
+            a.0.iter()
+                .zip(e.0.iter())
+                .map(|(a, e)| a.abs_diff(*e))
+                .max()
+                .unwrap_or(0);
+        max_difference = max_difference.max(difference);
+        let pixel = if difference > tolerance.per_channel {
+            mismatched += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let l = (luminance(e) * 0.3) as u8;
+            Rgba([l, l, l, 255])
+        };
+        diff.put_pixel(x, y, pixel);
+    }
+    Ok(Comparison {
+        mismatched,
+        mismatch_percent: 100.0 * mismatched as f32 / (w * h).max(1) as f32,
+        max_difference,
+        ssim: ssim(actual, expected)?,
+        diff,
+    })
+}
+
+/// Mean structural similarity of the luminance, over 8x8 windows
+/// with a stride of 4. 1.0 means identical.
+pub fn ssim(a: &RgbaImage, b: &RgbaImage) -> Result<f32, String> {
+    const WINDOW: u32 = 8;
+    const STRIDE: u32 = 4;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    if a.dimensions() != b.dimensions() {
+        return Err(format!(
+            "Image sizes differ: {:?} and {:?}",
+            a.dimensions(),
+            b.dimensions()
+        ));
+    }
+    let (w, h) = a.dimensions();
+    if w < WINDOW || h < WINDOW {
+        return Ok(if a == b { 1.0 } else { 0.0 });
+    }
+    let n = (WINDOW * WINDOW) as f64;
+    let mut total = 0.0;
+    let mut n_windows = 0;
+    for wy in (0..=h - WINDOW).step_by(STRIDE as usize) {
+        for wx in (0..=w - WINDOW).step_by(STRIDE as usize) {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in wy..wy + WINDOW {
+                for x in wx..wx + WINDOW {
+                    let la = luminance(a.get_pixel(x, y)) as f64;
+                    let lb = luminance(b.get_pixel(x, y)) as f64;
+                    sa += la;
+                    sb += lb;
+                    saa += la * la;
+                    sbb += lb * lb;
+                    sab += la * lb;
+                }
+            }
+            let (ma, mb) = (sa / n, sb / n);
+            let va = saa / n - ma * ma;
+            let vb = sbb / n - mb * mb;
+            let cov = sab / n - ma * mb;
+            total += ((2.0 * ma * mb + C1) * (2.0 * cov + C2))
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+            n_windows += 1;
+        }
+    }
+    Ok((total / n_windows as f64) as f32)
+}
+
+/// Compares with the png at `reference`. On failure the rendered
+/// image and the diff are written next to it, as
+/// `<name>.actual.png` and `<name>.diff.png`.
+pub fn match_reference(
+    actual: &RgbaImage,
+    reference: impl AsRef<Path>,
+    tolerance: &Tolerance,
+) -> Result<(), String> {
+    let reference = reference.as_ref();
+    let update = std::env::var_os(UPDATE_REFERENCES_VAR).is_some();
+    let save = |img: &RgbaImage, path: &Path| {
+        img.save(path)
+            .map_err(|e| format!("Could not save '{:?}': {}", path, e))
+    };
+
+    if !reference.exists() {
+        if update {
+            return save(actual, reference);
+        }
+        return Err(format!(
+            "Reference '{:?}' does not exist, set {} to create it",
+            reference, UPDATE_REFERENCES_VAR
+        ));
+    }
+    let expected = image::open(reference)
+        .map_err(|e| format!("Could not open reference '{:?}': {}", reference, e))?
+        .into_rgba8();
+
+    let cmp = compare(actual, &expected, tolerance)?;
+    if cmp.passes(tolerance) {
+        return Ok(());
+    }
+    if update {
+        return save(actual, reference);
+    }
+    save(actual, &sibling(reference, "actual"))?;
+    save(&cmp.diff, &sibling(reference, "diff"))?;
+    Err(format!(
+        "Render does not match '{:?}': {} pixels ({:.3}%) differ, max difference {}, ssim {:.4}",
+        reference, cmp.mismatched, cmp.mismatch_percent, cmp.max_difference, cmp.ssim
+    ))
+}
+
+/// Makes a GL context, and lets `draw` render into a bound rgba8 +
+/// depth/stencil framebuffer of `size`. Returns color attachment 0.
+///
+/// Note: the context is headless with the `headless` feature, else
+///       it belongs to a hidden window which still needs a display.
+pub fn render_offscreen<F>(size: (u32, u32), draw: F) -> Result<RgbaImage, String>
+where
+    F: FnOnce(&FrameBuffer),
+{
+    #[cfg(feature = "headless")]
+    let context = crate::headless::HeadlessContext::new()?;
+    #[cfg(not(feature = "headless"))]
+    let context = crate::window::GlfwWindow::hidden(size.0 as isize, size.1 as isize, "pgl test");
+    let fbo = FrameBuffer::builder(size.0, size.1)
+        .color(Format::Rgba8)
+        .depth(Format::Depth24Stencil8)
+        .build()?;
+    fbo.bind();
+    crate::utils::gl::viewport(0, 0, size.0 as usize, size.1 as usize);
+    fbo.clear();
+    draw(&fbo);
+    crate::utils::gl::finish();
+    FrameBuffer::unbind();
+    let img = fbo.read_image(0)?;
+    drop(fbo);
+    drop(context);
+    Ok(img)
+}
+
+fn luminance(p: &Rgba<u8>) -> f32 {
+    0.2126 * p.0[0] as f32 + 0.7152 * p.0[1] as f32 + 0.0722 * p.0[2] as f32
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    path.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a diagonal gradient with some structure for ssim to pick up
+    fn pattern(w: u32, h: u32, shift: u32) -> RgbaImage {
+        RgbaImage::from_fn(w, h, |x, y| {
+            let v = ((x + shift) * 7 + y * 3) % 256;
+            let check = if ((x + shift) / 4 + y / 4) & 1 == 0 {
+                255
+            } else {
+                0
+            };
+            Rgba([v as u8, check, 128, 255])
+        })
+    }
+
+    fn tolerance(per_channel: u8, max_mismatch_percent: f32) -> Tolerance {
+        Tolerance {
+            per_channel,
+            max_mismatch_percent,
+            min_ssim: None,
+        }
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let img = pattern(32, 32, 0);
+        let cmp = compare(&img, &img, &Tolerance::default()).unwrap();
+        assert_eq!(cmp.mismatched, 0);
+        assert_eq!(cmp.max_difference, 0);
+        assert!((cmp.ssim - 1.0).abs() < 1e-6);
+        assert!(cmp.passes(&Tolerance::default()));
+    }
+
+    #[test]
+    fn per_channel_tolerance() {
+        let expected = RgbaImage::from_pixel(4, 4, Rgba([100, 100, 100, 255]));
+        let actual = RgbaImage::from_pixel(4, 4, Rgba([103, 100, 100, 255]));
+        let cmp = compare(&actual, &expected, &tolerance(3, 0.0)).unwrap();
+        assert_eq!(cmp.mismatched, 0);
+        assert_eq!(cmp.max_difference, 3);
+        let cmp = compare(&actual, &expected, &tolerance(2, 0.0)).unwrap();
+        assert_eq!(cmp.mismatched, 16);
+        assert!(!cmp.passes(&tolerance(2, 0.0)));
+    }
+
+    #[test]
+    fn mismatch_percentage() {
+        let expected = RgbaImage::new(10, 10);
+        let mut actual = expected.clone();
+        for x in 0..5 {
+            actual.put_pixel(x, 0, Rgba([255, 255, 255, 255]));
+        }
+        let cmp = compare(&actual, &expected, &tolerance(0, 0.0)).unwrap();
+        assert_eq!(cmp.mismatched, 5);
+        assert!((cmp.mismatch_percent - 5.0).abs() < 1e-4);
+        assert!(cmp.passes(&tolerance(0, 5.0)));
+        assert!(!cmp.passes(&tolerance(0, 4.9)));
+    }
+
+    #[test]
+    fn ssim_drops_for_shifted_image() {
+        let img = pattern(64, 64, 0);
+        let shifted = pattern(64, 64, 2);
+        let s = ssim(&img, &shifted).unwrap();
+        assert!(s < 0.9, "ssim {}", s);
+        let min_ssim = Tolerance {
+            min_ssim: Some(0.95),
+            ..tolerance(255, 100.0)
+        };
+        assert!(!compare(&shifted, &img, &min_ssim)
+            .unwrap()
+            .passes(&min_ssim));
+    }
+
+    #[test]
+    fn diff_marks_mismatches_red() {
+        let expected = RgbaImage::from_pixel(2, 1, Rgba([200, 200, 200, 255]));
+        let mut actual = expected.clone();
+        actual.put_pixel(1, 0, Rgba([0, 0, 0, 255]));
+        let cmp = compare(&actual, &expected, &Tolerance::default()).unwrap();
+        assert_eq!(cmp.diff.get_pixel(1, 0), &Rgba([255, 0, 0, 255]));
+        let faded = cmp.diff.get_pixel(0, 0);
+        assert!(faded.0[0] == faded.0[1] && faded.0[0] < 200);
+    }
+
+    #[test]
+    fn size_mismatch_is_an_error() {
+        assert!(compare(
+            &RgbaImage::new(2, 2),
+            &RgbaImage::new(2, 3),
+            &Tolerance::default()
+        )
+        .is_err());
+        assert!(ssim(&RgbaImage::new(16, 16), &RgbaImage::new(16, 8)).is_err());
+    }
+
+    #[test]
+    fn failing_reference_writes_actual_and_diff() {
+        let dir = std::env::temp_dir().join(format!("pgl-testing-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let reference = dir.join("quad.png");
+        pattern(16, 16, 0).save(&reference).unwrap();
+
+        let tolerance = Tolerance::default();
+        assert!(match_reference(&pattern(16, 16, 0), &reference, &tolerance).is_ok());
+        assert!(match_reference(&pattern(16, 16, 3), &reference, &tolerance).is_err());
+        assert!(dir.join("quad.actual.png").exists());
+        assert!(dir.join("quad.diff.png").exists());
+        assert!(match_reference(&pattern(16, 16, 0), dir.join("missing.png"), &tolerance).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "headless")]
+    #[test]
+    fn renders_without_a_display() {
+        let img = render_offscreen((16, 8), |_| unsafe {
+            gl::ClearColor(1.0, 0.0, 0.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        })
+        .unwrap();
+        assert_eq!(img.dimensions(), (16, 8));
+        assert_eq!(img.get_pixel(3, 5), &Rgba([255, 0, 0, 255]));
+    }
+}
@@ -1,3 +1,7 @@
+use crate::buffer::Buffer;
+use crate::compressed::CompressedImage;
+use crate::sampler::{self, Sampler};
+use crate::state;
 use gl;
 use std::os::raw::c_void;
 
@@ -19,7 +23,7 @@ impl Texture {
         unsafe {
             let mut id = 0;
             gl::GenTextures(1, &mut id);
-            gl::BindTexture(gl::TEXTURE_2D, id);
+            let _bound = state::scoped_texture_active(gl::TEXTURE_2D, id);
             gl::TexImage2D(
                 gl::TEXTURE_2D,
                 0,
@@ -35,58 +39,151 @@ impl Texture {
                 gl::GenerateMipmap(gl::TEXTURE_2D);
             }
             options.set();
-            gl::BindTexture(gl::TEXTURE_2D, 0);
             Self { id }
         }
     }
 
-    pub fn empty(options: Options, (width, height): (u32, u32)) -> Self {
+    /// Loads a KTX, KTX2 or DDS file with BCn, ETC2 or ASTC data.
+    pub fn from_compressed_path(path: &str, options: Options) -> Result<Self, String> {
+        let img = CompressedImage::from_path(path)?;
+        Self::from_compressed(&img, options)
+    }
+
+    /// Uploads all mip levels in the image, mipmaps are never generated
+    /// because that is not possible for compressed formats.
+    pub fn from_compressed(img: &CompressedImage, options: Options) -> Result<Self, String> {
+        if img.levels.is_empty() {
+            return Err("Compressed image has no mip levels".into());
+        }
+        if !img.format.is_supported() {
+            return Err(format!(
+                "Compressed format {:?} is not supported by the driver",
+                img.format
+            ));
+        }
+        let options = Options {
+            mipmap: options.mipmap && img.levels.len() > 1,
+            max_level: options.max_level.min(img.levels.len() as u32 - 1),
+            ..options
+        };
+        unsafe {
+            let mut id = 0;
+            gl::GenTextures(1, &mut id);
+            let _bound = state::scoped_texture_active(gl::TEXTURE_2D, id);
+            for (level, data) in img.levels.iter().enumerate() {
+                let (width, height) = img.level_dims(level as u32);
+                gl::CompressedTexImage2D(
+                    gl::TEXTURE_2D,
+                    level as i32,
+                    img.format.gl(),
+                    width as i32,
+                    height as i32,
+                    0,
+                    data.len() as i32,
+                    data.as_ptr() as *const c_void,
+                );
+            }
+            options.set();
+            Ok(Self { id })
+        }
+    }
+
+    pub fn empty(options: Options, size: (u32, u32)) -> Self {
+        Self::empty_with_format(options, size, Format::Rgba8)
+    }
+
+    /// F.i. float or depth textures to render into with a framebuffer.
+    pub fn empty_with_format(
+        options: Options,
+        (width, height): (u32, u32),
+        format: Format,
+    ) -> Self {
         unsafe {
             let mut id = 0;
             gl::GenTextures(1, &mut id);
-            gl::BindTexture(gl::TEXTURE_2D, id);
+            let _bound = state::scoped_texture_active(gl::TEXTURE_2D, id);
             gl::TexImage2D(
                 gl::TEXTURE_2D,
                 0,
-                gl::RGBA as i32,
+                format.internal() as i32,
                 width as i32,
                 height as i32,
                 0,
-                gl::RGBA,
-                gl::UNSIGNED_BYTE,
+                format.format(),
+                format.dtype(),
                 std::ptr::null(),
             );
             if options.mipmap {
                 gl::GenerateMipmap(gl::TEXTURE_2D);
             }
             options.set();
-            Self::unbind();
             Self { id }
         }
     }
 
-    fn bind(&self) {
+    /// Overwrites a rectangle of the base level with rgba8 data,
+    /// (x, y) is the lower left corner in texels.
+    pub fn update_region<T>(&self, img: &[T], pos: (u32, u32), size: (u32, u32)) {
+        assert!(
+            std::mem::size_of_val(img) >= size.0 as usize * size.1 as usize * 4,
+            "[ERROR] Image data is smaller than the region to update"
+        );
+        unsafe { self.update_region_raw(img.as_ptr() as *const c_void, pos, size) }
+    }
+
+    // With a pixel unpack buffer bound `src` is an offset into it.
+    pub(crate) unsafe fn update_region_raw(
+        &self,
+        src: *const c_void,
+        (x, y): (u32, u32),
+        (width, height): (u32, u32),
+    ) {
+        let _bound = state::scoped_texture_active(gl::TEXTURE_2D, self.id);
+        gl::TexSubImage2D(
+            gl::TEXTURE_2D,
+            0,
+            x as i32,
+            y as i32,
+            width as i32,
+            height as i32,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            src,
+        );
+    }
+
+    /// Recomputes the mip levels, f.i. after `update_region`.
+    pub fn generate_mipmap(&self) {
+        let _bound = state::scoped_texture_active(gl::TEXTURE_2D, self.id);
         unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
         }
     }
+
     pub fn unbind() {
-        unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, 0);
-        }
+        state::bind_texture_active(gl::TEXTURE_2D, 0);
     }
 
     // Note: do all the binding after each other before draw
     //       f.i. dont create new textures between bind_to and draw
     pub fn bind_to(&self, slot: u32) -> Result<(), &'static str> {
-        if slot >= 16 {
-            return Err("The textureslot cannot be higher than 16");
-        }
-        unsafe {
-            gl::ActiveTexture(gl::TEXTURE0 + slot);
-            self.bind();
-            gl::ActiveTexture(gl::TEXTURE0);
-        }
+        check_unit(slot)?;
+        state::bind_texture_to(slot, gl::TEXTURE_2D, self.id);
+        Ok(())
+    }
+
+    /// Like `bind_to`, but the texture previously bound to `slot`
+    /// is bound again when the guard drops.
+    pub fn bind_to_scoped(&self, slot: u32) -> Result<state::Scoped, &'static str> {
+        check_unit(slot)?;
+        Ok(state::scoped_texture(slot, gl::TEXTURE_2D, self.id))
+    }
+
+    /// Binds the texture and a sampler that overrides
+    /// the texture's own `Options` on the same slot.
+    pub fn bind_to_with_sampler(&self, slot: u32, sampler: &Sampler) -> Result<(), &'static str> {
+        self.bind_to(slot)?;
+        sampler.bind_to(slot);
         Ok(())
     }
 
@@ -101,21 +198,86 @@ impl Drop for Texture {
         unsafe {
             gl::DeleteTextures(1, &self.id as *const u32);
         }
+        state::deleted_texture(self.id);
+    }
+}
+
+/// A texture view of a buffer (`BufferType::Texture`), read in
+/// glsl with a samplerBuffer and texelFetch.
+pub struct BufferTexture {
+    id: u32,
+}
+
+impl BufferTexture {
+    /// `format` must be a color format, f.i. `Format::Rgba32F`.
+    pub fn new(buffer: &Buffer, format: Format) -> Self {
+        unsafe {
+            let mut id = 0;
+            gl::GenTextures(1, &mut id);
+            let _bound = state::scoped_texture_active(gl::TEXTURE_BUFFER, id);
+            gl::TexBuffer(gl::TEXTURE_BUFFER, format.internal(), buffer.id());
+            Self { id }
+        }
+    }
+
+    pub fn bind_to(&self, slot: u32) -> Result<(), &'static str> {
+        check_unit(slot)?;
+        state::bind_texture_to(slot, gl::TEXTURE_BUFFER, self.id);
+        Ok(())
+    }
+}
+
+impl Drop for BufferTexture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+        state::deleted_texture(self.id);
+    }
+}
+
+/// The number of texture units, queried once per context from
+/// GL_MAX_COMBINED_TEXTURE_IMAGE_UNITS.
+pub fn max_units() -> u32 {
+    state::max_texture_units()
+}
+
+fn check_unit(slot: u32) -> Result<(), &'static str> {
+    if slot >= max_units() {
+        return Err("The textureslot exceeds GL_MAX_COMBINED_TEXTURE_IMAGE_UNITS");
     }
+    Ok(())
 }
 
+#[derive(Copy, Clone, Debug)]
 pub struct Options {
-    pub wrapping: Wrapping,
-    pub filtering: Filtering,
+    pub wrap_s: Wrapping,
+    pub wrap_t: Wrapping,
+    pub min_filter: Filtering,
+    pub mag_filter: Filtering,
+    /// generate (or use the stored) mip levels
     pub mipmap: bool,
+    /// filtering between mip levels, only used with `mipmap`
+    pub mipmap_filter: Filtering,
+    /// 1.0 means no anisotropic filtering, higher values are
+    /// clamped to what EXT_texture_filter_anisotropic allows
+    pub max_anisotropy: f32,
+    pub base_level: u32,
+    pub max_level: u32,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
-            filtering: Filtering::Linear,
-            wrapping: Wrapping::Repeat,
+            wrap_s: Wrapping::Repeat,
+            wrap_t: Wrapping::Repeat,
+            min_filter: Filtering::Linear,
+            mag_filter: Filtering::Linear,
             mipmap: true,
+            mipmap_filter: Filtering::Linear,
+            max_anisotropy: 1.0,
+            base_level: 0,
+            max_level: 1000,
         }
     }
 }
@@ -123,61 +285,206 @@ impl Default for Options {
 // TODO: DROP FUNCTION
 
 impl Options {
-    fn set(&self) {
-        use Filtering::*;
-        use Wrapping::*;
+    /// Same wrapping on both axes.
+    pub fn wrapping(mut self, wrapping: Wrapping) -> Self {
+        self.wrap_s = wrapping;
+        self.wrap_t = wrapping;
+        self
+    }
+
+    /// Same filtering for minification, magnification and mip levels.
+    pub fn filtering(mut self, filtering: Filtering) -> Self {
+        self.min_filter = filtering;
+        self.mag_filter = filtering;
+        self.mipmap_filter = filtering;
+        self
+    }
+
+    pub fn mipmap(mut self, mipmap: bool) -> Self {
+        self.mipmap = mipmap;
+        self
+    }
+
+    pub fn anisotropy(mut self, max_anisotropy: f32) -> Self {
+        self.max_anisotropy = max_anisotropy;
+        self
+    }
+
+    /// A texture has a single border colour, the
+    /// first `Wrapping::Constant` axis decides it.
+    pub fn border_color(&self) -> Option<[f32; 4]> {
+        self.wrap_s
+            .border_color()
+            .or_else(|| self.wrap_t.border_color())
+    }
+
+    pub fn mipmap_filter(&self) -> Option<Filtering> {
+        if self.mipmap {
+            Some(self.mipmap_filter)
+        } else {
+            None
+        }
+    }
 
+    fn set(&self) {
+        let target = gl::TEXTURE_2D;
         unsafe {
-            let wrap = match self.wrapping {
-                Repeat => gl::REPEAT,
-                MirrorRepeat => gl::MIRRORED_REPEAT,
-                ClampEdge => gl::CLAMP_TO_EDGE,
-                Constant(r, g, b) => {
-                    let c = &[r, g, b, 1.0] as *const f32;
-                    gl::TexParameterfv(gl::TEXTURE_2D, gl::TEXTURE_BORDER_COLOR, c);
-                    gl::CLAMP_TO_BORDER
-                }
-            } as i32;
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap);
-            match self.filtering {
-                Linear => {
-                    gl::TexParameteri(
-                        gl::TEXTURE_2D,
-                        gl::TEXTURE_MIN_FILTER,
-                        if self.mipmap {
-                            gl::LINEAR_MIPMAP_LINEAR as i32
-                        } else {
-                            gl::LINEAR as i32
-                        },
-                    );
-                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-                }
-                Nearest => {
-                    gl::TexParameteri(
-                        gl::TEXTURE_2D,
-                        gl::TEXTURE_MIN_FILTER,
-                        if self.mipmap {
-                            gl::NEAREST_MIPMAP_LINEAR as i32
-                        } else {
-                            gl::NEAREST as i32
-                        },
-                    );
-                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
-                }
-            };
+            gl::TexParameteri(target, gl::TEXTURE_WRAP_S, self.wrap_s.gl() as i32);
+            gl::TexParameteri(target, gl::TEXTURE_WRAP_T, self.wrap_t.gl() as i32);
+            if let Some(color) = self.border_color() {
+                gl::TexParameterfv(target, gl::TEXTURE_BORDER_COLOR, color.as_ptr());
+            }
+            gl::TexParameteri(
+                target,
+                gl::TEXTURE_MIN_FILTER,
+                self.min_filter.gl_min(self.mipmap_filter()) as i32,
+            );
+            gl::TexParameteri(target, gl::TEXTURE_MAG_FILTER, self.mag_filter.gl() as i32);
+            gl::TexParameteri(target, gl::TEXTURE_BASE_LEVEL, self.base_level as i32);
+            gl::TexParameteri(target, gl::TEXTURE_MAX_LEVEL, self.max_level as i32);
+            // drivers without EXT_texture_filter_anisotropic raise an error
+            let max_anisotropy = sampler::max_anisotropy();
+            if max_anisotropy > 1.0 {
+                let anisotropy = self.max_anisotropy.max(1.0).min(max_anisotropy);
+                gl::TexParameterf(target, sampler::TEXTURE_MAX_ANISOTROPY, anisotropy);
+            }
         }
     }
 }
 
+/// Sized internal formats, with the pixel transfer
+/// format and type used when uploading or reading back.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    R8,
+    Rg8,
+    Rgba8,
+    Srgb8Alpha8,
+    R16F,
+    Rg16F,
+    Rgba16F,
+    R32F,
+    Rg32F,
+    Rgba32F,
+    R11G11B10F,
+    Depth16,
+    Depth24,
+    Depth32F,
+    Depth24Stencil8,
+    Depth32FStencil8,
+}
+
+impl Format {
+    pub fn internal(&self) -> u32 {
+        use Format::*;
+
+        match self {
+            R8 => gl::R8,
+            Rg8 => gl::RG8,
+            Rgba8 => gl::RGBA8,
+            Srgb8Alpha8 => gl::SRGB8_ALPHA8,
+            R16F => gl::R16F,
+            Rg16F => gl::RG16F,
+            Rgba16F => gl::RGBA16F,
+            R32F => gl::R32F,
+            Rg32F => gl::RG32F,
+            Rgba32F => gl::RGBA32F,
+            R11G11B10F => gl::R11F_G11F_B10F,
+            Depth16 => gl::DEPTH_COMPONENT16,
+            Depth24 => gl::DEPTH_COMPONENT24,
+            Depth32F => gl::DEPTH_COMPONENT32F,
+            Depth24Stencil8 => gl::DEPTH24_STENCIL8,
+            Depth32FStencil8 => gl::DEPTH32F_STENCIL8,
+        }
+    }
+
+    pub fn format(&self) -> u32 {
+        use Format::*;
+
+        match self {
+            R8 | R16F | R32F => gl::RED,
+            Rg8 | Rg16F | Rg32F => gl::RG,
+            Rgba8 | Srgb8Alpha8 | Rgba16F | Rgba32F => gl::RGBA,
+            R11G11B10F => gl::RGB,
+            Depth16 | Depth24 | Depth32F => gl::DEPTH_COMPONENT,
+            Depth24Stencil8 | Depth32FStencil8 => gl::DEPTH_STENCIL,
+        }
+    }
+
+    pub fn dtype(&self) -> u32 {
+        use Format::*;
+
+        match self {
+            R8 | Rg8 | Rgba8 | Srgb8Alpha8 => gl::UNSIGNED_BYTE,
+            Depth16 | Depth24 => gl::UNSIGNED_INT,
+            Depth24Stencil8 => gl::UNSIGNED_INT_24_8,
+            Depth32FStencil8 => gl::FLOAT_32_UNSIGNED_INT_24_8_REV,
+            _ => gl::FLOAT,
+        }
+    }
+
+    pub fn is_depth(&self) -> bool {
+        matches!(self.format(), gl::DEPTH_COMPONENT | gl::DEPTH_STENCIL)
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        self.format() == gl::DEPTH_STENCIL
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Wrapping {
     Repeat,
     MirrorRepeat,
     ClampEdge,
-    Constant(f32, f32, f32),
+    /// clamp to a border of this rgba colour
+    Constant(f32, f32, f32, f32),
 }
 
+impl Wrapping {
+    pub fn gl(&self) -> u32 {
+        match self {
+            Self::Repeat => gl::REPEAT,
+            Self::MirrorRepeat => gl::MIRRORED_REPEAT,
+            Self::ClampEdge => gl::CLAMP_TO_EDGE,
+            Self::Constant(..) => gl::CLAMP_TO_BORDER,
+        }
+    }
+
+    pub fn border_color(&self) -> Option<[f32; 4]> {
+        match *self {
+            Self::Constant(r, g, b, a) => Some([r, g, b, a]),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Filtering {
     Linear,
     Nearest,
 }
+
+impl Filtering {
+    pub fn gl(&self) -> u32 {
+        match self {
+            Self::Linear => gl::LINEAR,
+            Self::Nearest => gl::NEAREST,
+        }
+    }
+
+    /// The minification filter, with `mipmap` selecting
+    /// the filtering between mip levels (if any).
+    pub fn gl_min(&self, mipmap: Option<Filtering>) -> u32 {
+        use Filtering::*;
+
+        match (self, mipmap) {
+            (Linear, None) => gl::LINEAR,
+            (Nearest, None) => gl::NEAREST,
+            (Linear, Some(Linear)) => gl::LINEAR_MIPMAP_LINEAR,
+            (Linear, Some(Nearest)) => gl::LINEAR_MIPMAP_NEAREST,
+            (Nearest, Some(Linear)) => gl::NEAREST_MIPMAP_LINEAR,
+            (Nearest, Some(Nearest)) => gl::NEAREST_MIPMAP_NEAREST,
+        }
+    }
+}
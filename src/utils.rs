@@ -24,6 +24,31 @@ pub mod gl {
         }
     }
 
+    /// The (major, minor) version of the current context.
+    pub fn version() -> (u32, u32) {
+        let mut major = 0;
+        let mut minor = 0;
+        unsafe {
+            gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+            gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+        }
+        (major as u32, minor as u32)
+    }
+
+    /// Whether the driver exposes an extension, f.i.
+    /// "GL_ARB_pipeline_statistics_query".
+    pub fn has_extension(name: &str) -> bool {
+        let mut n = 0;
+        unsafe {
+            gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut n);
+        }
+        (0..n as u32).any(|i| unsafe {
+            let ext = gl::GetStringi(gl::EXTENSIONS, i);
+            !ext.is_null()
+                && std::ffi::CStr::from_ptr(ext as *const _).to_bytes() == name.as_bytes()
+        })
+    }
+
     pub fn finish() {
         unsafe { gl::Finish() }
     }
@@ -49,14 +74,17 @@ pub mod gl {
             );
         }
     }
+    /// Depth testing and alpha blending, see `renderstate::RenderState`
+    /// for anything else.
     pub fn set_default_options() {
+        use crate::renderstate::{Blend, Depth, RenderState};
         unsafe {
             gl::ClearColor(0.0, 0.0, 0.0, 1.0);
-            gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LESS);
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
         }
+        RenderState::default()
+            .depth(Some(Depth::default()))
+            .blend(Some(Blend::alpha()))
+            .apply_all();
     }
 
     pub fn clear() {
@@ -1,6 +1,6 @@
 use crate::{
     buffer::{Buffer, BufferType, DrawType},
-    DType, GlslDType,
+    state, DType, GlslDType,
 };
 use std::collections::HashMap;
 
@@ -20,53 +20,55 @@ pub struct VertexArray {
     vao: u32,
     vbos: HashMap<String, Buffer>,
     pub ibo: Buffer,
-    drawtype: DrawType,
+    vertex_usage: DrawType,
 }
 
 impl VertexArray {
-    pub fn new(drawtype: DrawType) -> Self {
+    /// The usage hints of the vertex buffers and of the index buffer.
+    pub fn new(vertex_usage: DrawType, index_usage: DrawType) -> Self {
         let vbos = HashMap::new();
         let mut vao: u32 = 0;
         unsafe {
             gl::GenVertexArrays(1, &mut vao);
-            gl::BindVertexArray(vao);
         };
+        state::bind_vertex_array(vao);
 
-        let ibo = Buffer::new(BufferType::Index, drawtype.clone());
+        let ibo = Buffer::new(BufferType::Index, index_usage);
         ibo.bind(); // the bound vao saves ibo whenever bind is called
 
         Self {
             vao,
             vbos,
             ibo,
-            drawtype,
+            vertex_usage,
         }
     }
     pub fn new_static() -> Self {
-        Self::new(DrawType::Static)
+        Self::new(DrawType::Static, DrawType::Static)
     }
 
     pub fn new_dynamic() -> Self {
-        Self::new(DrawType::Dynamic)
+        Self::new(DrawType::Dynamic, DrawType::Dynamic)
     }
 
     pub fn bind(&self) {
-        unsafe {
-            gl::BindVertexArray(self.vao);
-        }
+        state::bind_vertex_array(self.vao);
     }
 
     pub fn unbind() {
-        unsafe {
-            gl::BindVertexArray(0);
-        }
+        state::bind_vertex_array(0);
+    }
+
+    /// Binds until the guard drops, then the previous vertex array.
+    pub fn bind_scoped(&self) -> state::Scoped {
+        state::scoped_vertex_array(self.vao)
     }
 
     pub fn new_vertex_buffer_filled<T>(&mut self, name: &str, content: &[T])
     where
         T: HasVertexAttributes,
     {
-        let vbo = Buffer::new(BufferType::Vertex, self.drawtype);
+        let mut vbo = Buffer::new(BufferType::Vertex, self.vertex_usage);
         vbo.bind();
         vbo.buffer(content);
         T::set_layouts();
@@ -77,7 +79,7 @@ impl VertexArray {
     where
         T: HasVertexAttributes,
     {
-        let vbo = Buffer::new(BufferType::Vertex, self.drawtype);
+        let mut vbo = Buffer::new(BufferType::Vertex, self.vertex_usage);
         vbo.bind();
         vbo.init(n_vertices * std::mem::size_of::<T>());
         T::set_layouts();
@@ -88,26 +90,30 @@ impl VertexArray {
         self.vbos.remove(name);
     }
 
-    pub fn buffer_indices<T>(&self, indices: &[T]) {
+    pub fn buffer_indices<T>(&mut self, indices: &[T]) {
         self.ibo.bind();
         self.ibo.buffer(indices);
     }
 
+    /// Offsets are in elements (vertices and indices), the
+    /// vertex and index buffer each get their own.
     pub fn subbuffer<V, I>(
         &mut self,
         vbo_name: &str,
         vertices: &[V],
+        vertex_offset: usize,
         indices: &[I],
-        offset: usize,
+        index_offset: usize,
     ) {
         self.ibo.bind();
-        self.ibo.subbuffer(indices, offset);
+        self.ibo
+            .subbuffer(indices, index_offset * std::mem::size_of::<I>());
         let vbo = self
             .vbos
             .get(vbo_name)
             .expect("[ERROR] Do not know this vbo name");
         vbo.bind();
-        vbo.subbuffer(vertices, offset);
+        vbo.subbuffer(vertices, vertex_offset * std::mem::size_of::<V>());
     }
 }
 
@@ -116,6 +122,7 @@ impl Drop for VertexArray {
         unsafe {
             gl::DeleteVertexArrays(1, &self.vao as *const u32);
         }
+        state::deleted_vertex_array(self.vao);
     }
 }
 
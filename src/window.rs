@@ -1,3 +1,4 @@
+use crate::framebuffer::DefaultFrameBuffer;
 use glfw::ffi::*;
 use std::ptr::null_mut;
 
@@ -13,9 +14,18 @@ impl Default for GlfwWindow {
 
 impl GlfwWindow {
     pub fn new(w: isize, h: isize, des: &str) -> Self {
+        Self::create(w, h, des, true)
+    }
+    /// A window that is never shown, f.i. to render offscreen
+    /// in tests. Still needs a display (or Xvfb) to make a context.
+    pub fn hidden(w: isize, h: isize, des: &str) -> Self {
+        Self::create(w, h, des, false)
+    }
+    fn create(w: isize, h: isize, des: &str, visible: bool) -> Self {
         unsafe {
             assert!(glfwInit() == 1);
             Self::set_hints(3, 2);
+            glfwWindowHint(VISIBLE, visible as i32);
             glfwSetErrorCallback(Some(glfw_error_callback));
 
             let window = glfwCreateWindow(
@@ -54,6 +64,8 @@ impl GlfwWindow {
     }
     pub fn make_current(&self) {
         unsafe { glfwMakeContextCurrent(self.window) }
+        // the cached bindings were of the previous context
+        crate::state::invalidate();
     }
     pub fn set_window_size(&self, w: isize, h: isize) {
         unsafe {
@@ -98,6 +110,13 @@ impl GlfwWindow {
         }
         (w as u32, h as u32)
     }
+    pub fn default_framebuffer(&self) -> DefaultFrameBuffer {
+        DefaultFrameBuffer::new(self.framebuffer_size())
+    }
+    /// Saves the back buffer, so call this before `swap_buffers`.
+    pub fn save_png(&self, path: impl AsRef<std::path::Path>) -> Result<(), String> {
+        self.default_framebuffer().save_png(path)
+    }
     pub fn hidpi_factor(&self) -> f32 {
         self.framebuffer_size().0 as f32 / self.window_size().0 as f32
     }